@@ -1,10 +1,229 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use crate::{decimal::Precision, fx, Datestamp, Statement};
 
 pub type ID = string_interner::DefaultSymbol;
-pub type Money = f64;
+/// An amount of money, exact to a fixed number of decimal places.
+pub type Money = crate::Decimal;
+/// A currency or other unit an account is held in, interned alongside the account names.
+pub type Commodity = ID;
 
 pub type Interner = string_interner::DefaultStringInterner;
 
+/// Separates the levels of a hierarchical account name, as in `assets:bank:current`.
+pub const SEPARATOR: char = ':';
+
+/// The name of the account directly above `name` in the hierarchy, if it has one.
+pub fn parent(name: &str) -> Option<&str> {
+    name.rsplit_once(SEPARATOR).map(|(parent, _)| parent)
+}
+
+/// A known balance for an account, at the start of `date` before any of its events, or at the
+/// start of the timeline without one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Opening {
+    pub balance: Money,
+    pub date: Option<Datestamp>,
+}
+
+/// What an account represents, which decides the sign its balance should have.
+///
+/// Money moves out of the first account of a statement and into the second, so income and
+/// borrowing leave their accounts below zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Asset,
+    Liability,
+    Income,
+    Expense,
+    Equity,
+}
+
+impl Kind {
+    /// Whether a balance below zero is expected, rather than a sign of money flowing backwards.
+    pub fn is_credit(self) -> bool {
+        matches!(self, Self::Liability | Self::Income | Self::Equity)
+    }
+
+    /// Whether `balance` has the sign expected of the kind, counting zero as either.
+    pub fn expects(self, balance: Money) -> bool {
+        balance.is_zero() || balance.is_negative() == self.is_credit()
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Asset => "asset",
+            Self::Liability => "liability",
+            Self::Income => "income",
+            Self::Expense => "expense",
+            Self::Equity => "equity",
+        })
+    }
+}
+
+/// An account whose balance has the opposite sign to the one its kind expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WrongSign {
+    pub account: ID,
+    pub kind: Kind,
+    pub balance: Money,
+}
+
+/// A bound on the balance of an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// The lowest balance allowed, such as an overdraft limit
+    Floor(Money),
+    /// The highest balance allowed, such as an allowance cap
+    Ceiling(Money),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Floor(floor) => write!(f, ">= {floor}"),
+            Self::Ceiling(ceiling) => write!(f, "<= {ceiling}"),
+        }
+    }
+}
+
+/// The lowest and highest balance an account may have.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    pub floor: Option<Money>,
+    pub ceiling: Option<Money>,
+}
+
+impl Limits {
+    /// The limit that changing `balance` by `change` takes it beyond, if any.
+    /// A change towards the limits is allowed, even if it does not reach them.
+    pub fn broken(&self, balance: Money, change: Money) -> Option<Limit> {
+        let after = balance + change;
+        match (self.floor, self.ceiling) {
+            (Some(floor), _) if change.is_negative() && after < floor => Some(Limit::Floor(floor)),
+            (_, Some(ceiling)) if change > Money::ZERO && after > ceiling => {
+                Some(Limit::Ceiling(ceiling))
+            }
+            _ => None,
+        }
+    }
+
+    /// The part of `change` that keeps `balance` within the limits, or gets no further from them.
+    pub fn allowed(&self, balance: Money, change: Money) -> Money {
+        match self.broken(balance, change) {
+            Some(Limit::Floor(floor)) => change.max((floor - balance).min(Money::ZERO)),
+            Some(Limit::Ceiling(ceiling)) => change.min((ceiling - balance).max(Money::ZERO)),
+            None => change,
+        }
+    }
+}
+
+/// What to do with a statement that would take an account beyond its limits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Policy {
+    /// Make the transfer in full, and record the violation
+    #[default]
+    Warn,
+    /// Make as much of the transfer as the limits allow
+    Clamp,
+    /// Skip the transfer and stop processing
+    Abort,
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(Self::Warn),
+            "clamp" => Ok(Self::Clamp),
+            "abort" => Ok(Self::Abort),
+            _ => Err(format!(
+                "unknown policy `{s}`, expected warn, clamp or abort"
+            )),
+        }
+    }
+}
+
+/// A statement that would have taken an account beyond one of its limits.
+#[derive(Debug, Clone, Copy)]
+pub struct Violation<'e> {
+    pub date: Datestamp,
+    /// The index of the event the statement belongs to
+    pub event: usize,
+    pub statement: &'e Statement,
+    pub account: ID,
+    pub limit: Limit,
+    /// The balance the statement would have left, before any policy was applied
+    pub balance: Money,
+}
+
+/// Everything declared about an account besides its name.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Declaration {
+    pub opening: Option<Opening>,
+    pub commodity: Option<Commodity>,
+    /// Accounts without a kind take the kind of the nearest account above them
+    pub kind: Option<Kind>,
+    pub limits: Limits,
+}
+
+/// The declarations of every account, by ID, the rates between their commodities, and the
+/// precision every posted amount is rounded to.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Registry {
+    declarations: HashMap<ID, Declaration>,
+    rates: fx::Rates,
+    precision: Precision,
+}
+
+impl Registry {
+    pub fn declare(&mut self, account: ID) -> &mut Declaration {
+        self.declarations.entry(account).or_default()
+    }
+
+    pub fn get(&self, account: ID) -> Option<&Declaration> {
+        self.declarations.get(&account)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ID, &Declaration)> {
+        self.declarations.iter().map(|(id, decl)| (*id, decl))
+    }
+
+    pub fn commodity(&self, account: ID) -> Option<Commodity> {
+        self.get(account)?.commodity
+    }
+
+    pub fn limits(&self, account: ID) -> Limits {
+        self.get(account)
+            .map(|decl| decl.limits)
+            .unwrap_or_default()
+    }
+
+    pub fn rates(&self) -> &fx::Rates {
+        &self.rates
+    }
+
+    pub fn rates_mut(&mut self) -> &mut fx::Rates {
+        &mut self.rates
+    }
+
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    pub fn set_precision(&mut self, precision: Precision) {
+        self.precision = precision;
+    }
+
+    pub fn openings(&self) -> impl Iterator<Item = (ID, Opening)> + '_ {
+        self.iter()
+            .filter_map(|(id, decl)| Some((id, decl.opening?)))
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub(crate) struct Symbols {
     new_: ID,
@@ -58,6 +277,19 @@ impl Stack {
         self.children.pop();
     }
 
+    /// The account `account` refers to, unless it is a layer.
+    pub(crate) fn account(&self, account: ID) -> Option<ID> {
+        match self.resolve(account) {
+            StackID::Account(id) => Some(id),
+            StackID::Layer(_) => None,
+        }
+    }
+
+    /// Whether two names refer to the same account or layer.
+    pub(crate) fn same(&self, a: ID, b: ID) -> bool {
+        self.resolve(a) == self.resolve(b)
+    }
+
     fn resolve(&self, account: ID) -> StackID {
         if account == self.symbols.super_ {
             self.children[self.children.len() - 2]
@@ -75,15 +307,14 @@ impl Stack {
     }
 
     pub(crate) fn merge(&mut self, stacks: impl Iterator<Item = Self>) {
-        let mut deltas: HashMap<StackID, f64> = HashMap::default();
+        let mut deltas: HashMap<StackID, Money> = HashMap::default();
         for stack in stacks {
             for (acid, balance) in stack.balances.into_iter() {
-                *deltas.entry(acid).or_insert(0.0) +=
-                    balance - self.balances.get(&acid).unwrap_or(&0.0);
+                *deltas.entry(acid).or_default() += balance - self[acid];
             }
         }
         for (acid, delta) in deltas.into_iter() {
-            *self.balances.entry(acid).or_insert(0.0) += delta;
+            self[acid] += delta;
         }
     }
 
@@ -114,7 +345,7 @@ impl std::ops::Index<StackID> for Stack {
     type Output = Money;
 
     fn index(&self, index: StackID) -> &Self::Output {
-        self.balances.get(&index).unwrap_or(&0.0)
+        self.balances.get(&index).unwrap_or(&Money::ZERO)
     }
 }
 
@@ -128,7 +359,7 @@ impl std::ops::Index<ID> for Stack {
 
 impl std::ops::IndexMut<StackID> for Stack {
     fn index_mut(&mut self, index: StackID) -> &mut Self::Output {
-        self.balances.entry(index).or_insert(0.0)
+        self.balances.entry(index).or_default()
     }
 }
 
@@ -149,6 +380,7 @@ impl<'s, 'i> CtxMut<'s, 'i> {
     }
 }
 
+#[allow(dead_code)]
 impl CtxMut<'_, '_> {
     pub(crate) fn register(&mut self, account: &str) -> ID {
         self.interner.get_or_intern(account)
@@ -0,0 +1,164 @@
+use crate::{account, Event, Expression, Schedule, Statement, Statements};
+
+/// A transfer between two accounts by name, as in `from > amount COMMODITY > to: label`.
+#[derive(Debug, Clone)]
+pub struct Transfer<'a> {
+    pub from: &'a str,
+    pub amount: Expression,
+    pub to: &'a str,
+    /// The commodity `amount` is measured in, otherwise that of `from`, or failing that `to`
+    pub commodity: Option<&'a str>,
+    pub label: Option<&'a str>,
+}
+
+impl<'a> Transfer<'a> {
+    pub fn new(from: &'a str, amount: impl Into<Expression>, to: &'a str) -> Self {
+        Self {
+            from,
+            amount: amount.into(),
+            to,
+            commodity: None,
+            label: None,
+        }
+    }
+
+    pub fn commodity(mut self, commodity: &'a str) -> Self {
+        self.commodity = Some(commodity);
+        self
+    }
+
+    pub fn label(mut self, label: &'a str) -> Self {
+        self.label = Some(label);
+        self
+    }
+}
+
+/// Builds the statements of a list or set, interning account names as it goes.
+pub struct StatementsBuilder<'i> {
+    accounts: &'i mut account::Interner,
+    statements: Vec<Statements>,
+}
+
+impl<'i> StatementsBuilder<'i> {
+    fn new(accounts: &'i mut account::Interner) -> Self {
+        Self {
+            accounts,
+            statements: Vec::new(),
+        }
+    }
+
+    /// The ID of an account or commodity, for use in expressions.
+    pub fn id(&mut self, name: &str) -> account::ID {
+        self.accounts.get_or_intern(name)
+    }
+
+    /// The balance of an account, as in `<name>`.
+    pub fn balance(&mut self, name: &str) -> Expression {
+        Expression::Account(self.id(name))
+    }
+
+    pub fn transfer(&mut self, transfer: Transfer) -> &mut Self {
+        let statement = Statement {
+            from: self.id(transfer.from),
+            to: self.id(transfer.to),
+            func: transfer.amount.into_operation(),
+            commodity: transfer.commodity.map(|commodity| self.id(commodity)),
+            label: transfer.label.map(Into::into),
+        };
+        self.statement(statement)
+    }
+
+    /// A statement built by hand, such as one with an operation that is not an expression.
+    pub fn statement(&mut self, statement: Statement) -> &mut Self {
+        self.statements.push(Statements::Single(statement));
+        self
+    }
+
+    /// Nested statements run in order on `account`, or on `self` without one, as in
+    /// `<account> [...]`.
+    pub fn list(
+        &mut self,
+        account: Option<&str>,
+        build: impl FnOnce(&mut StatementsBuilder),
+    ) -> &mut Self {
+        let statements = self.nested(account, build, Statements::list);
+        self.statements.push(statements);
+        self
+    }
+
+    /// Nested statements run side by side on `account`, or on a `new` layer without one, as in
+    /// `<account> {...}`.
+    pub fn set(
+        &mut self,
+        account: Option<&str>,
+        build: impl FnOnce(&mut StatementsBuilder),
+    ) -> &mut Self {
+        let statements = self.nested(account, build, Statements::set);
+        self.statements.push(statements);
+        self
+    }
+
+    fn nested(
+        &mut self,
+        account: Option<&str>,
+        build: impl FnOnce(&mut StatementsBuilder),
+        group: fn(&mut account::Interner, Option<account::ID>, Vec<Statements>) -> Statements,
+    ) -> Statements {
+        let account = account.map(|name| self.id(name));
+        let mut nested = StatementsBuilder::new(self.accounts);
+        build(&mut nested);
+        let statements = nested.statements;
+        group(self.accounts, account, statements)
+    }
+}
+
+/// Builds an [`Event`] in code, with the same meaning as the source that compiles to it.
+pub struct EventBuilder<'i> {
+    accounts: &'i mut account::Interner,
+    schedule: Schedule,
+    event_accounts: Vec<account::ID>,
+}
+
+impl<'i> EventBuilder<'i> {
+    pub fn new(accounts: &'i mut account::Interner, schedule: Schedule) -> Self {
+        Self {
+            accounts,
+            schedule,
+            event_accounts: Vec::new(),
+        }
+    }
+
+    /// An account the statements run for, in turn, as in `<a, b>` before the event.
+    pub fn account(mut self, name: &str) -> Self {
+        self.event_accounts.push(self.accounts.get_or_intern(name));
+        self
+    }
+
+    /// The event, with statements run in order, as in `(schedule) <account> [...]`.
+    pub fn list(self, account: Option<&str>, build: impl FnOnce(&mut StatementsBuilder)) -> Event {
+        self.build(account, build, Statements::list)
+    }
+
+    /// The event, with statements run side by side, as in `(schedule) <account> {...}`.
+    pub fn set(self, account: Option<&str>, build: impl FnOnce(&mut StatementsBuilder)) -> Event {
+        self.build(account, build, Statements::set)
+    }
+
+    fn build(
+        self,
+        account: Option<&str>,
+        build: impl FnOnce(&mut StatementsBuilder),
+        group: fn(&mut account::Interner, Option<account::ID>, Vec<Statements>) -> Statements,
+    ) -> Event {
+        let Self {
+            accounts,
+            schedule,
+            event_accounts,
+        } = self;
+        Event {
+            schedule,
+            accounts: event_accounts,
+            operations: StatementsBuilder::new(accounts).nested(account, build, group),
+        }
+    }
+}
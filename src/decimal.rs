@@ -0,0 +1,316 @@
+use std::{fmt, iter::Sum, ops, str::FromStr};
+
+/// How to round a value that falls between two that can be represented.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Rounding {
+    /// To the nearest, with ties to the even neighbour
+    #[default]
+    HalfEven,
+    /// To the nearest, with ties away from zero
+    HalfUp,
+    /// Towards zero
+    Down,
+    /// Towards negative infinity
+    Floor,
+    /// Towards positive infinity
+    Ceiling,
+}
+
+/// A number of decimal places and how to round to them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Precision {
+    /// Negative places round to tens, hundreds and so on
+    pub places: i32,
+    pub rounding: Rounding,
+}
+
+impl Default for Precision {
+    /// Pennies, with ties to the even penny.
+    fn default() -> Self {
+        Self {
+            places: 2,
+            rounding: Rounding::HalfEven,
+        }
+    }
+}
+
+impl Precision {
+    pub fn round(&self, value: Decimal) -> Decimal {
+        value.round(self.places, self.rounding)
+    }
+}
+
+/// The value held by [`Decimal::ONE`].
+const SCALE: i128 = 10i128.pow(Decimal::PLACES);
+
+/// A fixed point decimal number.
+/// Values are exact to [`Decimal::PLACES`] decimal places, and results that need more are rounded
+/// half to even.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Decimal(i128);
+
+/// Text that is not a decimal number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDecimalError;
+
+impl fmt::Display for ParseDecimalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid decimal number")
+    }
+}
+
+impl std::error::Error for ParseDecimalError {}
+
+/// `numerator / denominator`, rounded to a whole number.
+fn divide(numerator: i128, denominator: i128, rounding: Rounding) -> i128 {
+    let (quotient, remainder) = (numerator / denominator, numerator % denominator);
+    if remainder == 0 {
+        return quotient;
+    }
+    let negative = (numerator < 0) != (denominator < 0);
+    let half = (remainder.unsigned_abs() * 2).cmp(&denominator.unsigned_abs());
+    let away = match rounding {
+        Rounding::HalfEven => half.then(quotient.rem_euclid(2).cmp(&0)).is_gt(),
+        Rounding::HalfUp => half.is_ge(),
+        Rounding::Down => false,
+        Rounding::Floor => negative,
+        Rounding::Ceiling => !negative,
+    };
+    match (away, negative) {
+        (false, _) => quotient,
+        (true, false) => quotient + 1,
+        (true, true) => quotient - 1,
+    }
+}
+
+impl Decimal {
+    /// The number of decimal places every value is held to.
+    pub const PLACES: u32 = 12;
+    pub const ZERO: Self = Self(0);
+    pub const ONE: Self = Self(SCALE);
+    pub const MAX: Self = Self(i128::MAX);
+    pub const MIN: Self = Self(-i128::MAX);
+
+    /// Round to a number of decimal places, or to tens, hundreds and so on if negative.
+    pub fn round(self, places: i32, rounding: Rounding) -> Self {
+        let Some(coarser) = (Self::PLACES as i32).checked_sub(places) else {
+            return self;
+        };
+        if coarser <= 0 {
+            return self;
+        }
+        match 10i128.checked_pow(coarser as u32) {
+            Some(factor) => match divide(self.0, factor, rounding).checked_mul(factor) {
+                Some(value) => Self(value),
+                // Rounding away from zero went out of range, so go towards it instead
+                None => Self(divide(self.0, factor, Rounding::Down) * factor),
+            },
+            None => Self::ZERO,
+        }
+    }
+
+    /// A result, or the largest value of its sign if it overflowed.
+    fn saturate(value: Option<i128>, negative: bool) -> Self {
+        match value {
+            Some(value) => Self(value.max(Self::MIN.0)),
+            None if negative => Self::MIN,
+            None => Self::MAX,
+        }
+    }
+
+    /// The whole part, rounded towards zero.
+    pub fn trunc(self) -> i128 {
+        self.0 / SCALE
+    }
+
+    pub fn abs(self) -> Self {
+        Self(self.0.abs())
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0 < 0
+    }
+}
+
+impl From<i32> for Decimal {
+    fn from(value: i32) -> Self {
+        Self(i128::from(value) * SCALE)
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(value: i64) -> Self {
+        Self(i128::from(value) * SCALE)
+    }
+}
+
+impl From<u32> for Decimal {
+    fn from(value: u32) -> Self {
+        Self(i128::from(value) * SCALE)
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = ParseDecimalError;
+
+    /// Digits with an optional sign and fractional part, rounded half to even to
+    /// [`Decimal::PLACES`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if whole.is_empty()
+            || !whole.bytes().all(|b| b.is_ascii_digit())
+            || !fraction.bytes().all(|b| b.is_ascii_digit())
+        {
+            return Err(ParseDecimalError);
+        }
+        let whole = whole
+            .parse::<i128>()
+            .ok()
+            .and_then(|whole| whole.checked_mul(SCALE))
+            .ok_or(ParseDecimalError)?;
+
+        // Keep one digit past the places to round on, and note whether any more are non-zero
+        let places = Self::PLACES as usize;
+        let kept = fraction.get(..=places).unwrap_or(fraction);
+        let sticky =
+            fraction.len() > places + 1 && fraction[places + 1..].bytes().any(|b| b != b'0');
+        let mut part = 0i128;
+        for digit in kept.bytes() {
+            part = part * 10 + i128::from(digit - b'0');
+        }
+        let part = match kept.len() {
+            len if len <= places => part * 10i128.pow((places - len) as u32),
+            _ => {
+                let rounded = divide(part, 10, Rounding::HalfEven);
+                // A tie that is broken by later digits rounds up
+                if sticky && part % 10 == 5 && rounded == part / 10 {
+                    rounded + 1
+                } else {
+                    rounded
+                }
+            }
+        };
+        let value = whole.checked_add(part).ok_or(ParseDecimalError)?;
+        Ok(Self(if negative { -value } else { value }))
+    }
+}
+
+impl fmt::Display for Decimal {
+    /// Without a precision, as many places as are needed.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match f.precision() {
+            Some(places) => self.round(places as i32, Rounding::HalfEven),
+            None => *self,
+        };
+        let (whole, fraction) = (
+            value.0.unsigned_abs() / SCALE as u128,
+            value.0.unsigned_abs() % SCALE as u128,
+        );
+        let fraction = format!("{fraction:0width$}", width = Self::PLACES as usize);
+        let fraction = match f.precision() {
+            Some(places) => &fraction[..places.min(fraction.len())],
+            None => fraction.trim_end_matches('0'),
+        };
+        let digits = if fraction.is_empty() {
+            whole.to_string()
+        } else {
+            format!("{whole}.{fraction}")
+        };
+        f.pad_integral(value.0 >= 0, "", &digits)
+    }
+}
+
+impl fmt::Debug for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl ops::Neg for Decimal {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self(-self.0)
+    }
+}
+
+impl ops::Add for Decimal {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self::saturate(self.0.checked_add(rhs.0), rhs.0 < 0)
+    }
+}
+
+impl ops::Sub for Decimal {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self::saturate(self.0.checked_sub(rhs.0), rhs.0 > 0)
+    }
+}
+
+impl ops::Mul for Decimal {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        // Split the right hand side so the intermediate product stays in range
+        let (whole, fraction) = (rhs.0 / SCALE, rhs.0 % SCALE);
+        let fraction = match self.0.checked_mul(fraction) {
+            Some(product) => divide(product, SCALE, Rounding::HalfEven),
+            None => divide(self.0, SCALE, Rounding::HalfEven) * fraction,
+        };
+        let product = self
+            .0
+            .checked_mul(whole)
+            .and_then(|product| product.checked_add(fraction));
+        Self::saturate(product, (self.0 < 0) != (rhs.0 < 0))
+    }
+}
+
+impl ops::Div for Decimal {
+    type Output = Self;
+
+    /// Division by zero gives zero, as there is nothing to share out.
+    fn div(self, rhs: Self) -> Self {
+        if rhs.0 == 0 {
+            return Self::ZERO;
+        }
+        let (quotient, remainder) = (self.0 / rhs.0, self.0 % rhs.0);
+        let fraction = match remainder.checked_mul(SCALE) {
+            Some(remainder) => divide(remainder, rhs.0, Rounding::HalfEven),
+            // Only a divisor this large leaves a remainder this large
+            None => divide(remainder, rhs.0 / SCALE, Rounding::HalfEven),
+        };
+        let quotient = quotient
+            .checked_mul(SCALE)
+            .and_then(|quotient| quotient.checked_add(fraction));
+        Self::saturate(quotient, (self.0 < 0) != (rhs.0 < 0))
+    }
+}
+
+impl ops::AddAssign for Decimal {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl ops::SubAssign for Decimal {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sum for Decimal {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, ops::Add::add)
+    }
+}
@@ -8,24 +8,36 @@ pub struct Event {
 }
 
 impl Event {
+    /// The dates of every event from `from` until, but not including, `to`, in order, with the
+    /// index of each event in `events`.
     pub fn timeline<'a>(
         events: impl Iterator<Item = &'a Event>,
         from: Datestamp,
-    ) -> impl Iterator<Item = (Datestamp, &'a Event)> {
-        crate::schedule::event_queue(events.map(|e| (&e.schedule, e)), from)
+        to: Datestamp,
+    ) -> impl Iterator<Item = (Datestamp, (usize, &'a Event))> {
+        crate::schedule::event_queue(
+            events
+                .enumerate()
+                .map(|(index, e)| (&e.schedule, (index, e))),
+            from,
+            to,
+        )
     }
 }
 
+// Not yet produced by the timeline, which reports `transaction::Source` for now
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct TransactionView<'a, 'e> {
     date: Datestamp,
     from: account::ID,
     to: account::ID,
-    amount: f64,
+    amount: account::Money,
     description: Option<&'e str>,
     interner: &'a crate::account::Interner,
 }
 
+#[allow(dead_code)]
 impl<'a, 'e> TransactionView<'a, 'e> {
     fn from(&self) -> &str {
         self.interner.resolve(self.from).unwrap()
@@ -36,12 +48,13 @@ impl<'a, 'e> TransactionView<'a, 'e> {
     }
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct Transaction {
     pub date: Datestamp,
     pub from: String,
     pub to: String,
-    pub amount: f64,
+    pub amount: account::Money,
     pub description: Option<String>,
 }
 
@@ -0,0 +1,170 @@
+use crate::{account, decimal::Rounding, Decimal, Operation};
+
+/// An arithmetic expression over account balances.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    /// A constant value
+    Number(Decimal),
+    /// The balance of an account
+    Account(account::ID),
+    Neg(Box<Expression>),
+    Add(Box<Expression>, Box<Expression>),
+    Sub(Box<Expression>, Box<Expression>),
+    Mul(Box<Expression>, Box<Expression>),
+    Div(Box<Expression>, Box<Expression>),
+    /// A built-in function applied to its arguments
+    Call(Function, Vec<Expression>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Function {
+    /// The smallest of its arguments
+    Min,
+    /// The largest of its arguments
+    Max,
+    /// The absolute value
+    Abs,
+    /// Round half away from zero, optionally to a number of decimal places
+    Round,
+    /// Round down, optionally to a number of decimal places
+    Floor,
+    /// Round up, optionally to a number of decimal places
+    Ceil,
+    /// `clamp(value, low, high)`
+    Clamp,
+}
+
+impl Function {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "min" => Self::Min,
+            "max" => Self::Max,
+            "abs" => Self::Abs,
+            "round" => Self::Round,
+            "floor" => Self::Floor,
+            "ceil" => Self::Ceil,
+            "clamp" => Self::Clamp,
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Abs => "abs",
+            Self::Round => "round",
+            Self::Floor => "floor",
+            Self::Ceil => "ceil",
+            Self::Clamp => "clamp",
+        }
+    }
+
+    /// The number of arguments the function accepts.
+    pub fn arity(&self) -> std::ops::RangeInclusive<usize> {
+        match self {
+            Self::Min | Self::Max => 1..=usize::MAX,
+            Self::Abs => 1..=1,
+            Self::Round | Self::Floor | Self::Ceil => 1..=2,
+            Self::Clamp => 3..=3,
+        }
+    }
+
+    fn apply(&self, args: &[Decimal]) -> Decimal {
+        let places = |rounding| {
+            let places = args
+                .get(1)
+                .map_or(0, |places| places.trunc().clamp(-64, 64));
+            args[0].round(places as i32, rounding)
+        };
+        match self {
+            Self::Min => args.iter().copied().min().unwrap_or_default(),
+            Self::Max => args.iter().copied().max().unwrap_or_default(),
+            Self::Abs => args[0].abs(),
+            Self::Round => places(Rounding::HalfUp),
+            Self::Floor => places(Rounding::Floor),
+            Self::Ceil => places(Rounding::Ceiling),
+            Self::Clamp => args[0].max(args[1]).min(args[2]),
+        }
+    }
+}
+
+impl Expression {
+    pub fn eval(&self, ctx: &account::CtxMut) -> Decimal {
+        match self {
+            Expression::Number(value) => *value,
+            Expression::Account(id) => ctx[*id],
+            Expression::Neg(expr) => -expr.eval(ctx),
+            Expression::Add(lhs, rhs) => lhs.eval(ctx) + rhs.eval(ctx),
+            Expression::Sub(lhs, rhs) => lhs.eval(ctx) - rhs.eval(ctx),
+            Expression::Mul(lhs, rhs) => lhs.eval(ctx) * rhs.eval(ctx),
+            Expression::Div(lhs, rhs) => lhs.eval(ctx) / rhs.eval(ctx),
+            Expression::Call(func, args) => {
+                func.apply(&args.iter().map(|arg| arg.eval(ctx)).collect::<Vec<_>>())
+            }
+        }
+    }
+
+    /// The value of the expression, if it does not depend on any account.
+    pub fn constant(&self) -> Option<Decimal> {
+        Some(match self {
+            Expression::Number(value) => *value,
+            Expression::Account(_) => return None,
+            Expression::Neg(expr) => -expr.constant()?,
+            Expression::Add(lhs, rhs) => lhs.constant()? + rhs.constant()?,
+            Expression::Sub(lhs, rhs) => lhs.constant()? - rhs.constant()?,
+            Expression::Mul(lhs, rhs) => lhs.constant()? * rhs.constant()?,
+            Expression::Div(lhs, rhs) => lhs.constant()? / rhs.constant()?,
+            Expression::Call(func, args) => func.apply(
+                &args
+                    .iter()
+                    .map(Expression::constant)
+                    .collect::<Option<Vec<_>>>()?,
+            ),
+        })
+    }
+
+    pub fn into_operation(self) -> Operation {
+        Box::new(move |ctx| self.eval(ctx))
+    }
+
+    /// The expression as a percentage, as in `5%`.
+    pub fn percent(self) -> Self {
+        Expression::Div(
+            Box::new(self),
+            Box::new(Expression::Number(Decimal::from(100))),
+        )
+    }
+
+    /// The expression as a percentage of the balance of `account`, as in `5 % savings`.
+    pub fn percent_of(self, account: account::ID) -> Self {
+        Expression::Mul(
+            Box::new(self.percent()),
+            Box::new(Expression::Account(account)),
+        )
+    }
+
+    /// The expression multiplied by `numerator / denominator`, as in `100 y/m`.
+    /// Multiplying first keeps whole ratios exact.
+    pub fn ratio(self, numerator: i32, denominator: i32) -> Self {
+        Expression::Div(
+            Box::new(Expression::Mul(
+                Box::new(self),
+                Box::new(Expression::Number(Decimal::from(numerator))),
+            )),
+            Box::new(Expression::Number(Decimal::from(denominator))),
+        )
+    }
+}
+
+impl From<Decimal> for Expression {
+    fn from(value: Decimal) -> Self {
+        Expression::Number(value)
+    }
+}
+
+impl From<i32> for Expression {
+    fn from(value: i32) -> Self {
+        Expression::Number(Decimal::from(value))
+    }
+}
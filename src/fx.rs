@@ -0,0 +1,123 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::{
+    account::{self, Commodity, Money},
+    Datestamp, Decimal,
+};
+
+/// A conversion the rate table could not make.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MissingRate {
+    pub from: Commodity,
+    pub to: Commodity,
+}
+
+/// Exchange rates between commodities, each effective from a date until replaced.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Rates {
+    /// The value of one `base` in `quote`, by `(base, quote)`
+    rates: HashMap<(Commodity, Commodity), BTreeMap<Datestamp, Decimal>>,
+}
+
+impl Rates {
+    /// Set the value of one `base` in `quote`, from `date` or for all time.
+    pub fn insert(
+        &mut self,
+        base: Commodity,
+        quote: Commodity,
+        rate: Decimal,
+        date: Option<Datestamp>,
+    ) {
+        self.rates
+            .entry((base, quote))
+            .or_default()
+            .insert(date.unwrap_or(Datestamp::MIN), rate);
+    }
+
+    /// Load a table of rates, one `BASE,QUOTE,RATE[,DATE]` per line.
+    /// Blank lines and lines starting with `#` are ignored.
+    pub fn load(&mut self, source: &str, interner: &mut account::Interner) -> Result<(), String> {
+        for (number, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| format!("line {}: {message}", number + 1);
+            let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+            let [base, quote, rate, rest @ ..] = fields.as_slice() else {
+                return Err(error("expected `BASE,QUOTE,RATE[,DATE]`"));
+            };
+            let rate = rate
+                .parse()
+                .map_err(|_| error(&format!("invalid rate `{rate}`")))?;
+            let date = match rest {
+                [] => None,
+                [date] => Some(
+                    Datestamp::parse_from_str(date, "%Y-%m-%d")
+                        .map_err(|_| error(&format!("invalid date `{date}`")))?,
+                ),
+                _ => return Err(error("expected `BASE,QUOTE,RATE[,DATE]`")),
+            };
+            self.insert(
+                interner.get_or_intern(base),
+                interner.get_or_intern(quote),
+                rate,
+                date,
+            );
+        }
+        Ok(())
+    }
+
+    fn direct(&self, from: Commodity, to: Commodity, date: Datestamp) -> Option<Decimal> {
+        let latest = |base, quote| {
+            self.rates
+                .get(&(base, quote))?
+                .range(..=date)
+                .next_back()
+                .map(|(_, rate)| *rate)
+        };
+        latest(from, to).or_else(|| latest(to, from).map(|rate| Decimal::ONE / rate))
+    }
+
+    /// The value of one `from` in `to` on `date`, crossing through one other commodity if needed.
+    pub fn rate(&self, from: Commodity, to: Commodity, date: Datestamp) -> Option<Decimal> {
+        if from == to {
+            return Some(Decimal::ONE);
+        }
+        self.direct(from, to, date).or_else(|| {
+            self.commodities()
+                .filter(|via| *via != from && *via != to)
+                .find_map(|via| Some(self.direct(from, via, date)? * self.direct(via, to, date)?))
+        })
+    }
+
+    /// Convert `amount` between commodities.
+    /// Amounts without a commodity on either side are left as they are.
+    pub fn convert(
+        &self,
+        amount: Money,
+        from: Option<Commodity>,
+        to: Option<Commodity>,
+        date: Datestamp,
+    ) -> Result<Money, MissingRate> {
+        match (from, to) {
+            (Some(from), Some(to)) => self
+                .rate(from, to, date)
+                .map(|rate| amount * rate)
+                .ok_or(MissingRate { from, to }),
+            _ => Ok(amount),
+        }
+    }
+
+    pub fn commodities(&self) -> impl Iterator<Item = Commodity> + '_ {
+        self.rates
+            .keys()
+            .flat_map(|(base, quote)| [*base, *quote])
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rates.is_empty()
+    }
+}
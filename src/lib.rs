@@ -1,9 +1,20 @@
 pub mod account;
 
+pub mod builder;
+pub use builder::{EventBuilder, StatementsBuilder};
+
+pub mod decimal;
+pub use decimal::Decimal;
+
 mod event;
 
+pub mod expression;
+pub use expression::Expression;
+
 pub use event::Event;
 
+pub mod fx;
+
 mod process;
 
 pub mod schedule;
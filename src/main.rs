@@ -1,8 +1,22 @@
 use mercury::Resolve;
-use std::{collections::HashMap, env, fs};
+use std::{collections::BTreeMap, env, fs};
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    // Options may appear anywhere, such as `--limits=clamp`
+    let (options, args): (Vec<String>, Vec<String>) =
+        env::args().partition(|arg| arg.starts_with("--"));
+    let policy = match options
+        .iter()
+        .find_map(|option| option.strip_prefix("--limits="))
+        .map(str::parse::<mercury::account::Policy>)
+        .transpose()
+    {
+        Ok(policy) => policy.unwrap_or_default(),
+        Err(error) => {
+            eprintln!("{}", error);
+            return;
+        }
+    };
     if args.len() < 2 {
         eprintln!("Please provide a file path as a command line argument");
         return;
@@ -19,6 +33,7 @@ fn main() {
     };
 
     let mut accounts = mercury::account::Interner::default();
+    let mut registry = mercury::account::Registry::default();
 
     let from = chrono::Local::now().date_naive();
     let to = chrono::Local::now()
@@ -26,35 +41,130 @@ fn main() {
         .checked_add_days(chrono::Days::new(365))
         .unwrap();
 
-    let events = mercury::syntax::compile(
-        mercury::syntax::Context {
-            accounts: &mut accounts,
-            date_start: from,
-            date_end: to,
-        },
+    let events = match mercury::syntax::compile_file(
+        mercury::syntax::Context::new(&mut accounts, &mut registry, from, to),
+        &mercury::syntax::Filesystem,
+        file_path,
         file_contents.as_str(),
-    );
-    let mut timeline = mercury::Timeline::new(&events, accounts);
+    ) {
+        Ok(events) => events,
+        Err(error) => {
+            eprintln!("{}", error);
+            return;
+        }
+    };
+    let mut timeline = mercury::Timeline::new(&events, accounts, registry);
+    timeline.set_policy(policy);
 
     {
         timeline.process(from, to).for_each(drop);
     }
-    let history = timeline.resolve(timeline.history());
+    for violation in timeline.violations() {
+        let label = match &violation.statement.label {
+            Some(label) => format!(" ({})", label),
+            None => String::new(),
+        };
+        eprintln!(
+            "warning: event {}{} on {} takes {} to {}, beyond its limit {}",
+            violation.event + 1,
+            label,
+            violation.date,
+            timeline.resolve(violation.account),
+            violation.balance,
+            violation.limit
+        );
+    }
+    if timeline.aborted() {
+        eprintln!("error: stopped at the first balance beyond its limit");
+    }
+    for &index in timeline.idle() {
+        let accounts = events[index]
+            .accounts
+            .iter()
+            .map(|acc| timeline.resolve(*acc))
+            .collect::<Vec<_>>();
+        eprintln!(
+            "warning: event {} for {} never occurs between {} and {}",
+            index + 1,
+            accounts.join(", "),
+            from,
+            to
+        );
+    }
+    for missing in timeline.missing_rates() {
+        eprintln!(
+            "warning: no rate from {} to {}, converted one to one",
+            timeline.resolve(missing.from),
+            timeline.resolve(missing.to)
+        );
+    }
+    for wrong in timeline.wrong_signs() {
+        eprintln!(
+            "warning: {} account {} ends with a balance of {}, money flowed the wrong way",
+            wrong.kind,
+            timeline.resolve(wrong.account),
+            wrong.balance
+        );
+    }
+
+    // Report in a base currency, if one was given after the file
+    let base = match args.get(2) {
+        Some(base) => match timeline.lookup(base) {
+            Some(base) => Some(base),
+            None => {
+                eprintln!("Unknown base currency: {}", base);
+                return;
+            }
+        },
+        None => None,
+    };
+    let no_rate = |missing: mercury::fx::MissingRate| {
+        eprintln!(
+            "No rate from {} to {}",
+            timeline.resolve(missing.from),
+            timeline.resolve(missing.to)
+        );
+    };
+    let converted = match base.map(|base| timeline.history_in(base)).transpose() {
+        Ok(converted) => converted,
+        Err(missing) => return no_rate(missing),
+    };
+    let history = timeline.resolve(converted.as_ref().unwrap_or(timeline.history()));
     let dates = timeline.dates();
     let full_history = history
         .into_iter()
         .map(|(acc, (_, balances))| {
             (acc, {
-                let mut out = vec![0.0; dates.len() - balances.len()];
+                let mut out = vec![mercury::Decimal::ZERO; dates.len() - balances.len()];
                 out.reserve_exact(balances.len());
                 out.extend(balances);
                 out
             })
         })
-        .collect::<HashMap<_, _>>();
+        .collect::<BTreeMap<_, _>>();
 
+    // Sorted by name, so each account is followed by the accounts below it
     for (acc, balances) in full_history {
         println!("{}: {:?}", acc, balances);
     }
     println!("Dates: {:?}", dates);
+
+    // Totals by kind, for ledgers that declare them
+    if timeline
+        .registry()
+        .iter()
+        .any(|(_, decl)| decl.kind.is_some())
+    {
+        let totals = [
+            ("Net worth", timeline.net_worth(base)),
+            ("Income", timeline.income(base)),
+            ("Expenses", timeline.expenses(base)),
+        ];
+        for (name, total) in totals {
+            match total {
+                Ok(total) => println!("{}: {:?}", name, total),
+                Err(missing) => return no_rate(missing),
+            }
+        }
+    }
 }
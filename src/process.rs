@@ -1,44 +1,189 @@
-use crate::{account, Datestamp, Event, Statements};
+use std::collections::{BTreeSet, HashMap};
 
-pub(crate) fn event<'e, 'i>(
-    _date: Datestamp,
-    event: &'e Event,
+use crate::{account, decimal::Rounding, fx, Datestamp, Event, Statement, Statements};
+
+/// Converts between the commodities of accounts on the date being processed, and holds each
+/// transfer to the limits of the accounts.
+pub(crate) struct Exchange<'r, 'e> {
+    date: Datestamp,
+    /// The index of the event being processed
+    event: usize,
+    registry: &'r account::Registry,
+    /// Conversions without a rate, which are made one to one
+    missing: &'r mut BTreeSet<fx::MissingRate>,
+    policy: account::Policy,
+    violations: &'r mut Vec<account::Violation<'e>>,
+    /// Changes made by earlier statements of the sets being processed, which the stack of a
+    /// later statement does not see
+    pending: HashMap<account::ID, account::Money>,
+}
+
+impl<'e> Exchange<'_, 'e> {
+    fn commodity(
+        &self,
+        stack: &account::Stack,
+        account: account::ID,
+    ) -> Option<account::Commodity> {
+        self.registry.commodity(stack.account(account)?)
+    }
+
+    fn round(&self, amount: account::Money) -> account::Money {
+        self.registry.precision().round(amount)
+    }
+
+    /// The amount in another commodity, rounded to the precision of a posting.
+    fn convert(
+        &mut self,
+        amount: account::Money,
+        from: Option<account::Commodity>,
+        to: Option<account::Commodity>,
+    ) -> account::Money {
+        let converted = self
+            .registry
+            .rates()
+            .convert(amount, from, to, self.date)
+            .unwrap_or_else(|missing| {
+                self.missing.insert(missing);
+                amount
+            });
+        self.round(converted)
+    }
+
+    /// Whether a violation has stopped processing.
+    fn halted(&self) -> bool {
+        self.policy == account::Policy::Abort && !self.violations.is_empty()
+    }
+
+    /// Record the limit that changing `account` by `change` breaks, if any, and return the
+    /// fraction of the change the limits allow.
+    fn check(
+        &mut self,
+        stack: &account::Stack,
+        statement: &'e Statement,
+        account: account::ID,
+        change: account::Money,
+    ) -> account::Money {
+        let Some(id) = stack.account(account) else {
+            return account::Money::ONE;
+        };
+        let limits = self.registry.limits(id);
+        let balance = stack[account] + self.pending.get(&id).copied().unwrap_or_default();
+        let Some(limit) = limits.broken(balance, change) else {
+            return account::Money::ONE;
+        };
+        self.violations.push(account::Violation {
+            date: self.date,
+            event: self.event,
+            statement,
+            account: id,
+            limit,
+            balance: balance + change,
+        });
+        limits.allowed(balance, change) / change
+    }
+}
+
+/// What to do when a statement breaks the limits of an account, and where to record it.
+pub(crate) struct Constraints<'r, 'e> {
+    pub(crate) policy: account::Policy,
+    pub(crate) violations: &'r mut Vec<account::Violation<'e>>,
+}
+
+pub(crate) fn event<'e>(
+    date: Datestamp,
+    (index, event): (usize, &'e Event),
     stack: &mut account::Stack,
-    interner: &'i mut account::Interner,
+    interner: &mut account::Interner,
+    registry: &account::Registry,
+    missing: &mut BTreeSet<fx::MissingRate>,
+    constraints: Constraints<'_, 'e>,
 ) {
+    let mut exchange = Exchange {
+        date,
+        event: index,
+        registry,
+        missing,
+        policy: constraints.policy,
+        violations: constraints.violations,
+        pending: HashMap::new(),
+    };
     for account in &event.accounts {
         stack.push(*account);
-        statements(&event.operations, stack, interner);
+        statements(&event.operations, stack, interner, &mut exchange);
         stack.pop();
     }
 }
 
-pub(crate) fn statements(
-    stmts: &Statements,
+pub(crate) fn statements<'e>(
+    stmts: &'e Statements,
     stack: &mut account::Stack,
     interner: &mut account::Interner,
+    exchange: &mut Exchange<'_, 'e>,
 ) {
+    if exchange.halted() {
+        return;
+    }
     match stmts {
         Statements::List(acid, list) => {
             stack.push(*acid);
             for stmt in list {
-                statements(stmt, stack, interner);
+                statements(stmt, stack, interner, exchange);
             }
             stack.pop();
         }
         Statements::Set(acid, set) => {
             stack.push(*acid);
+            let balances = stack.balances().collect::<HashMap<_, _>>();
+            let pending = exchange.pending.clone();
             let mut shadows = stack.split(set.len());
             for (shadow, stmt) in shadows.iter_mut().zip(set.iter()) {
-                statements(stmt, shadow, interner);
+                statements(stmt, shadow, interner, exchange);
+                // Later statements are held to the limits together with what this one moved
+                for (id, balance) in shadow.balances() {
+                    let change = balance - balances.get(&id).copied().unwrap_or_default();
+                    if !change.is_zero() {
+                        *exchange.pending.entry(id).or_default() += change;
+                    }
+                }
             }
+            exchange.pending = pending;
             stack.merge(shadows.into_iter());
             stack.pop();
         }
         Statements::Single(stmt) => {
-            let delta = (stmt.func)(&mut account::CtxMut::new(stack, interner));
-            stack[stmt.from] -= delta;
-            stack[stmt.to] += delta;
+            // Round once per posting, so each transaction moves a whole number of the smallest unit
+            let delta = exchange.round((stmt.func)(&mut account::CtxMut::new(stack, interner)));
+            let from = exchange.commodity(stack, stmt.from);
+            let to = exchange.commodity(stack, stmt.to);
+            let unit = stmt.commodity.or(from).or(to);
+            let (debit, credit) = (
+                exchange.convert(delta, unit, from),
+                exchange.convert(delta, unit, to),
+            );
+            // Moving money to where it already is cannot break a limit
+            let allowed = if stack.same(stmt.from, stmt.to) {
+                account::Money::ONE
+            } else {
+                exchange
+                    .check(stack, stmt, stmt.from, -debit)
+                    .min(exchange.check(stack, stmt, stmt.to, credit))
+            };
+            let (debit, credit) = match exchange.policy {
+                _ if allowed == account::Money::ONE => (debit, credit),
+                account::Policy::Warn => (debit, credit),
+                account::Policy::Abort => return,
+                account::Policy::Clamp => {
+                    // Round down, so the part that is moved never breaks the limit
+                    let precision = exchange.registry.precision();
+                    let delta = (delta * allowed).round(precision.places, Rounding::Down);
+                    (
+                        exchange.convert(delta, unit, from),
+                        exchange.convert(delta, unit, to),
+                    )
+                }
+            };
+            stack[stmt.from] -= debit;
+            stack[stmt.to] += credit;
         }
     }
 }
@@ -1,14 +1,170 @@
+use std::fmt;
+
 use super::Datestamp;
+use chrono::{Datelike, Weekday};
 use itertools::Itertools;
 
+mod calendar;
+pub use calendar::{easter, Calendar, Holiday, Roll};
+
 pub type Cron = cron::Schedule;
 
+/// The earliest date a schedule is counted from, as the cron engine goes no earlier.
+pub const EPOCH: Datestamp = match Datestamp::from_ymd_opt(1970, 1, 1) {
+    Some(date) => date,
+    None => panic!("Epoch must be a valid date"),
+};
+
+/// A calendar length of time.
+/// Months and years clamp to the end of shorter months, so 31 January plus a month is 28 February.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Period {
+    Days(u32),
+    Weeks(u32),
+    Months(u32),
+    Years(u32),
+}
+
+impl Period {
+    /// The date this period after `date`.
+    pub fn after(&self, date: Datestamp) -> Option<Datestamp> {
+        match *self {
+            Period::Days(n) => date.checked_add_days(chrono::Days::new(n.into())),
+            Period::Weeks(n) => date.checked_add_days(chrono::Days::new(u64::from(n) * 7)),
+            Period::Months(n) => date.checked_add_months(chrono::Months::new(n)),
+            Period::Years(n) => date.checked_add_months(chrono::Months::new(n.checked_mul(12)?)),
+        }
+    }
+
+    /// This period repeated `count` times.
+    pub fn times(&self, count: u32) -> Option<Period> {
+        Some(match *self {
+            Period::Days(n) => Period::Days(n.checked_mul(count)?),
+            Period::Weeks(n) => Period::Weeks(n.checked_mul(count)?),
+            Period::Months(n) => Period::Months(n.checked_mul(count)?),
+            Period::Years(n) => Period::Years(n.checked_mul(count)?),
+        })
+    }
+
+    pub fn is_zero(&self) -> bool {
+        matches!(
+            self,
+            Period::Days(0) | Period::Weeks(0) | Period::Months(0) | Period::Years(0)
+        )
+    }
+
+    /// A number of whole periods from `anchor` that does not pass `date`.
+    fn count_until(&self, anchor: Datestamp, date: Datestamp) -> u32 {
+        if date <= anchor {
+            return 0;
+        }
+        let days = (date - anchor).num_days();
+        let months =
+            (date.year() - anchor.year()) * 12 + date.month() as i32 - anchor.month() as i32 - 1;
+        let count = match *self {
+            Period::Days(n) => days / i64::from(n),
+            Period::Weeks(n) => days / (i64::from(n) * 7),
+            Period::Months(n) => i64::from(months.max(0)) / i64::from(n),
+            Period::Years(n) => i64::from(months.max(0)) / (i64::from(n) * 12),
+        };
+        count.try_into().unwrap_or(u32::MAX)
+    }
+
+    /// The date this period before `date`.
+    pub fn before(&self, date: Datestamp) -> Option<Datestamp> {
+        match *self {
+            Period::Days(n) => date.checked_sub_days(chrono::Days::new(n.into())),
+            Period::Weeks(n) => date.checked_sub_days(chrono::Days::new(u64::from(n) * 7)),
+            Period::Months(n) => date.checked_sub_months(chrono::Months::new(n)),
+            Period::Years(n) => date.checked_sub_months(chrono::Months::new(n.checked_mul(12)?)),
+        }
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Period::Days(n) => write!(f, "{n}d"),
+            Period::Weeks(n) => write!(f, "{n}w"),
+            Period::Months(n) => write!(f, "{n}m"),
+            Period::Years(n) => write!(f, "{n}y"),
+        }
+    }
+}
+
+/// A period to move a date by, in either direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Shift {
+    Later(Period),
+    Earlier(Period),
+}
+
+impl Shift {
+    pub fn apply(&self, date: Datestamp) -> Option<Datestamp> {
+        match self {
+            Shift::Later(period) => period.after(date),
+            Shift::Earlier(period) => period.before(date),
+        }
+    }
+
+    /// Move `date` by the period in the opposite direction.
+    pub fn undo(&self, date: Datestamp) -> Option<Datestamp> {
+        match self {
+            Shift::Later(period) => period.before(date),
+            Shift::Earlier(period) => period.after(date),
+        }
+    }
+}
+
+impl fmt::Display for Shift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shift::Later(period) => write!(f, "+ {period}"),
+            Shift::Earlier(period) => write!(f, "- {period}"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum Schedule {
     /// Dates that appear in the cron schema
     Cron(Cron),
     /// An exact date
     Date(Datestamp),
+    /// Working days according to the calendar
+    Work(Calendar),
+    /// The holidays in the calendar
+    Holidays(Calendar),
+    /// The anchor and every whole step after it.
+    /// Each date is stepped from the anchor, so month steps from the 31st return to the 31st.
+    Every { anchor: Datestamp, step: Period },
+    /// The nth day of every month, counting from the end if negative
+    MonthDay(i8),
+    /// The nth weekday of every month, counting from the end if negative
+    Weekday { weekday: Weekday, nth: i8 },
+    /// The nth working day of every month according to the calendar, counting from the end if
+    /// negative
+    WorkDay(i8, Calendar),
+    /// Every day from the start to the end, as months and days, each year.
+    /// A season that ends before it starts wraps into the next year.
+    Season { start: (u32, u32), end: (u32, u32) },
+    /// The dates of the schedule each moved by the shift
+    Offset(Box<Schedule>, Shift),
+    /// The dates of the schedule moved to working days by the convention
+    Roll(Box<Schedule>, Roll, Calendar),
+    /// The first dates of the schedule, counted from its first date on or after the anchor
+    Take {
+        count: u32,
+        anchor: Datestamp,
+        schedule: Box<Schedule>,
+    },
+    /// All but the first dates of the schedule, counted from its first date on or after the anchor
+    Skip {
+        count: u32,
+        anchor: Datestamp,
+        schedule: Box<Schedule>,
+    },
     /// All dates that are not in the given schedule
     TimeFunctionNot(Box<Schedule>),
     /// All dates that are in either of the given schedules
@@ -17,70 +173,167 @@ pub enum Schedule {
     TimeFunctionAnd(Box<Schedule>, Box<Schedule>),
     /// The dates that are in the first schedule until the first date in the second schedule
     TimeFunctionBy(Box<Schedule>, Box<Schedule>),
-    /// The dates that are in the first schedule before the first date in the second schedule
+    /// The dates that are in the first schedule before the first date in the second schedule,
+    /// both counted from the start of the query
     TimeFunctionBefore(Box<Schedule>, Box<Schedule>),
-    /// The dates that are in the first schedule after the first date in the second schedule
+    /// The dates that are in the first schedule on or after the first date in the second schedule,
+    /// both counted from the start of the query
     TimeFunctionAfter(Box<Schedule>, Box<Schedule>),
 }
 
 impl Schedule {
+    /// The dates on or after `from`.
+    ///
+    /// Combinators search without end for dates their operands share, so this may never return
+    /// for a schedule with no dates left, such as `((3 * *) & (4 * *))`.
+    /// Use [`Schedule::upcoming_until`] or [`Schedule::between`] to bound the search.
     pub fn upcoming(&self, from: Datestamp) -> Box<dyn Iterator<Item = Datestamp> + '_> {
+        self.upcoming_until(from, Datestamp::MAX)
+    }
+
+    /// The dates on or after `from`, with combinators searching no further than `horizon`.
+    /// Dates on or after the horizon may still be returned, but only if they are found without
+    /// searching past it.
+    pub fn upcoming_until(
+        &self,
+        from: Datestamp,
+        horizon: Datestamp,
+    ) -> Box<dyn Iterator<Item = Datestamp> + '_> {
+        let within = move |date: &Datestamp| date < &horizon;
         match self {
+            // The cron engine only looks after a time, so start just before `from`
             Schedule::Cron(cron) => Box::new(
-                cron.after(
-                    &from
-                        .and_time(chrono::NaiveTime::from_hms_opt(12, 0, 0).unwrap())
-                        .and_utc(),
-                )
-                .map(|d| d.date_naive()),
+                cron.after(&(noon(from) - chrono::Duration::seconds(1)))
+                    .map(|d| d.date_naive()),
             ),
             Schedule::Date(date) => {
                 Box::new(std::iter::once(*date).filter(move |date| date >= &from))
             }
-            Schedule::TimeFunctionNot(schedule) => {
-                let mut upcoming = schedule.upcoming(from).peekable();
-                let mut all = from.iter_days().peekable();
-                Box::new(std::iter::from_fn(move || loop {
-                    if upcoming.peek() < all.peek() {
-                        upcoming.next()?;
-                    } else if upcoming.peek() == all.peek() {
-                        upcoming.next()?;
-                        all.next();
-                    } else {
-                        return all.next();
-                    }
-                }))
+            Schedule::Work(calendar) => Box::new(
+                from.iter_days()
+                    .filter(move |date| calendar.is_working_day(*date)),
+            ),
+            Schedule::Holidays(calendar) => calendar.upcoming(from),
+            Schedule::Every { anchor, step } => Box::new(
+                (step.count_until(*anchor, from)..)
+                    .map_while(move |count| step.times(count)?.after(*anchor))
+                    .skip_while(move |date| date < &from),
+            ),
+            Schedule::MonthDay(_) | Schedule::Weekday { .. } | Schedule::WorkDay(..) => Box::new(
+                (month_index(from)..=month_index(Datestamp::MAX))
+                    .filter_map(move |index| self.day_in_month(index))
+                    .skip_while(move |date| date < &from),
+            ),
+            Schedule::Season { .. } => Box::new(
+                from.iter_days()
+                    .filter(move |date| self.contains(from, *date)),
+            ),
+            Schedule::Offset(schedule, shift) => {
+                // Dates before `from` may be shifted onto or after it, and those after the
+                // horizon before it
+                let start = shift.undo(from).unwrap_or(Datestamp::MIN);
+                let horizon = shift
+                    .undo(horizon)
+                    .map_or(Datestamp::MAX, |undone| undone.max(horizon));
+                Box::new(
+                    schedule
+                        .upcoming_until(start.min(from), horizon)
+                        .filter_map(move |date| shift.apply(date))
+                        .dedup()
+                        .skip_while(move |date| date < &from),
+                )
             }
-            Schedule::TimeFunctionOr(schedule1, schedule2) => {
-                Box::new(schedule1.upcoming(from).merge(schedule2.upcoming(from)))
+            Schedule::Roll(schedule, convention, calendar) => {
+                // Dates in the run of days off just before `from` may roll forward onto it,
+                // and those in the run from the horizon may roll back before it
+                let start = days_off_before(calendar, from);
+                let horizon = horizon
+                    .iter_days()
+                    .take_while(|date| !calendar.is_working_day(*date))
+                    .last()
+                    .and_then(|date| date.succ_opt())
+                    .unwrap_or(horizon);
+                Box::new(
+                    schedule
+                        .upcoming_until(start, horizon)
+                        .filter_map(move |date| calendar.roll(date, *convention))
+                        .dedup()
+                        .skip_while(move |date| date < &from),
+                )
             }
-            Schedule::TimeFunctionAnd(schedule1, schedule2) => {
-                Box::new(schedule1.upcoming(from).same(schedule2.upcoming(from)))
+            Schedule::Take {
+                count,
+                anchor,
+                schedule,
+            } => Box::new(
+                schedule
+                    .upcoming_until(*anchor, horizon)
+                    .take(*count as usize)
+                    .skip_while(move |date| date < &from),
+            ),
+            Schedule::Skip {
+                count,
+                anchor,
+                schedule,
+            } => Box::new(
+                schedule
+                    .upcoming_until(*anchor, horizon)
+                    .skip(*count as usize)
+                    .skip_while(move |date| date < &from),
+            ),
+            Schedule::TimeFunctionNot(schedule) => {
+                let mut upcoming = schedule
+                    .upcoming_until(from, horizon)
+                    .take_while(within)
+                    .peekable();
+                Box::new(from.iter_days().take_while(within).filter(move |day| {
+                    while upcoming.next_if(|date| date < day).is_some() {}
+                    upcoming.peek() != Some(day)
+                }))
             }
+            Schedule::TimeFunctionOr(schedule1, schedule2) => Box::new(
+                schedule1
+                    .upcoming_until(from, horizon)
+                    .merge(schedule2.upcoming_until(from, horizon)),
+            ),
+            Schedule::TimeFunctionAnd(schedule1, schedule2) => Box::new(
+                schedule1
+                    .upcoming_until(from, horizon)
+                    .take_while(within)
+                    .same(schedule2.upcoming_until(from, horizon).take_while(within)),
+            ),
             Schedule::TimeFunctionBy(schedule_predicate, schedule_next) => {
-                let mut it_predicate = schedule_predicate.upcoming(from).peekable();
-                let mut it_next = schedule_next.upcoming(from);
+                // A date before the horizon is only known to be the last once the predicate is
+                // searched up to the next date that closes it
+                let closing = schedule_next
+                    .upcoming_until(horizon, horizon)
+                    .next()
+                    .map_or(horizon, |date| date.max(horizon));
+                let mut it_predicate = schedule_predicate
+                    .upcoming_until(from, closing)
+                    .take_while(move |date| date < &closing)
+                    .peekable();
+                let mut it_next = schedule_next.upcoming_until(from, horizon);
                 let mut previous = None;
                 Box::new(std::iter::from_fn(move || {
-                    while let Some(date_next) = it_next.next() {
-                        while let Some(date_predicate) = it_predicate.peek() {
-                            if date_predicate >= &date_next {
-                                let ret = previous;
-                                previous = it_predicate.next();
-                                return ret;
-                            } else {
-                                previous = it_predicate.next();
-                            }
+                    for date_next in it_next.by_ref() {
+                        while let Some(date) = it_predicate.next_if(|date| date < &date_next) {
+                            previous = Some(date);
+                        }
+                        if previous.is_some() {
+                            return previous.take();
                         }
+                        // Nothing left for any later date to close
+                        it_predicate.peek()?;
                     }
                     None
                 }))
             }
             Schedule::TimeFunctionBefore(schedule, predicate) => {
-                if let Some(before) = predicate.upcoming(from).next() {
+                if let Some(before) = predicate.upcoming_until(from, horizon).next() {
                     Box::new(
                         schedule
-                            .upcoming(from)
+                            .upcoming_until(from, horizon)
                             .take_while(move |date| date < &before),
                     )
                 } else {
@@ -88,10 +341,10 @@ impl Schedule {
                 }
             }
             Schedule::TimeFunctionAfter(schedule, predicate) => {
-                if let Some(before) = predicate.upcoming(from).next() {
+                if let Some(before) = predicate.upcoming_until(from, horizon).next() {
                     Box::new(
                         schedule
-                            .upcoming(from)
+                            .upcoming_until(from, horizon)
                             .skip_while(move |date| date < &before),
                     )
                 } else {
@@ -100,6 +353,505 @@ impl Schedule {
             }
         }
     }
+
+    /// The dates from `from` until, but not including, `to`.
+    pub fn between(&self, from: Datestamp, to: Datestamp) -> impl Iterator<Item = Datestamp> + '_ {
+        self.upcoming_until(from, to)
+            .take_while(move |date| date < &to)
+    }
+
+    /// Whether the schedule falls on `date` in a query from `from`.
+    /// Combinators search no further than the day after, as for [`Schedule::between`] to then.
+    pub fn contains(&self, from: Datestamp, date: Datestamp) -> bool {
+        let succ = date.succ_opt().unwrap_or(date);
+        date >= from
+            && match self {
+                Schedule::Cron(cron) => cron.includes(noon(date)),
+                Schedule::Date(day) => *day == date,
+                Schedule::Work(calendar) => calendar.is_working_day(date),
+                Schedule::Holidays(calendar) => calendar.is_holiday(date),
+                Schedule::Every { .. } => self.upcoming_until(date, succ).next() == Some(date),
+                Schedule::MonthDay(_) | Schedule::Weekday { .. } | Schedule::WorkDay(..) => {
+                    self.day_in_month(month_index(date)) == Some(date)
+                }
+                Schedule::Season { start, end } => {
+                    let day = &(date.month(), date.day());
+                    if start <= end {
+                        start <= day && day <= end
+                    } else {
+                        day >= start || day <= end
+                    }
+                }
+                Schedule::Offset(schedule, shift) => {
+                    // Months clamp, so several days may shift onto the same date
+                    let from = shift.undo(from).unwrap_or(Datestamp::MIN).min(from);
+                    shift.undo(date).is_some_and(|start| {
+                        start
+                            .iter_days()
+                            .map_while(|day| Some((day, shift.apply(day)?)))
+                            .take_while(|(_, shifted)| shifted <= &date)
+                            .any(|(day, shifted)| shifted == date && schedule.contains(from, day))
+                    })
+                }
+                Schedule::Roll(schedule, convention, calendar) => {
+                    // Only the date itself and the days off either side of it can roll onto it
+                    let from = days_off_before(calendar, from);
+                    let day_off = |day: &Datestamp| !calendar.is_working_day(*day);
+                    calendar.is_working_day(date)
+                        && std::iter::once(date)
+                            .chain(date.iter_days().rev().skip(1).take_while(day_off))
+                            .chain(date.iter_days().skip(1).take_while(day_off))
+                            .any(|day| {
+                                calendar.roll(day, *convention) == Some(date)
+                                    && schedule.contains(from, day)
+                            })
+                }
+                Schedule::Take {
+                    count,
+                    anchor,
+                    schedule,
+                } => {
+                    schedule.contains(*anchor, date)
+                        && schedule
+                            .upcoming_until(*anchor, succ)
+                            .take(*count as usize)
+                            .take_while(|day| day <= &date)
+                            .any(|day| day == date)
+                }
+                Schedule::Skip {
+                    count,
+                    anchor,
+                    schedule,
+                } => {
+                    schedule.contains(*anchor, date)
+                        && schedule
+                            .upcoming_until(*anchor, succ)
+                            .nth(*count as usize)
+                            .is_some_and(|first| first <= date)
+                }
+                Schedule::TimeFunctionNot(schedule) => !schedule.contains(from, date),
+                Schedule::TimeFunctionOr(schedule1, schedule2) => {
+                    schedule1.contains(from, date) || schedule2.contains(from, date)
+                }
+                Schedule::TimeFunctionAnd(schedule1, schedule2) => {
+                    schedule1.contains(from, date) && schedule2.contains(from, date)
+                }
+                Schedule::TimeFunctionBy(..) => {
+                    self.between(from, succ).find(|day| day >= &date) == Some(date)
+                }
+                Schedule::TimeFunctionBefore(schedule, predicate) => {
+                    schedule.contains(from, date)
+                        && predicate
+                            .upcoming_until(from, succ)
+                            .next()
+                            .is_some_and(|end| date < end)
+                }
+                Schedule::TimeFunctionAfter(schedule, predicate) => {
+                    schedule.contains(from, date)
+                        && predicate
+                            .upcoming_until(from, succ)
+                            .next()
+                            .is_some_and(|start| date >= start)
+                }
+            }
+    }
+
+    /// The dates from `from` until, but not including, `before`, latest first.
+    pub fn previous(
+        &self,
+        from: Datestamp,
+        before: Datestamp,
+    ) -> Box<dyn Iterator<Item = Datestamp> + '_> {
+        let days = move || {
+            before
+                .iter_days()
+                .rev()
+                .skip(1)
+                .take_while(move |date| date >= &from)
+        };
+        let since = move |date: &Datestamp| date >= &from;
+        match self {
+            Schedule::Cron(cron) => Box::new(
+                cron.after(&noon(before))
+                    .rev()
+                    .map(|d| d.date_naive())
+                    .take_while(since),
+            ),
+            Schedule::Date(date) => {
+                Box::new(std::iter::once(*date).filter(move |date| date < &before && date >= &from))
+            }
+            Schedule::Work(calendar) => {
+                Box::new(days().filter(move |date| calendar.is_working_day(*date)))
+            }
+            Schedule::Holidays(calendar) => {
+                Box::new(days().filter(move |date| calendar.is_holiday(*date)))
+            }
+            Schedule::Every { anchor, step } => Box::new(
+                (0..=step.count_until(*anchor, before).saturating_add(1))
+                    .rev()
+                    .filter_map(move |count| step.times(count)?.after(*anchor))
+                    .skip_while(move |date| date >= &before)
+                    .take_while(since),
+            ),
+            Schedule::MonthDay(_) | Schedule::Weekday { .. } | Schedule::WorkDay(..) => Box::new(
+                (month_index(from)..=month_index(before))
+                    .rev()
+                    .filter_map(move |index| self.day_in_month(index))
+                    .skip_while(move |date| date >= &before)
+                    .take_while(since),
+            ),
+            Schedule::Season { .. } => {
+                Box::new(days().filter(move |date| self.contains(from, *date)))
+            }
+            Schedule::Offset(schedule, shift) => {
+                let start = shift.undo(from).unwrap_or(Datestamp::MIN).min(from);
+                let end = shift
+                    .undo(before)
+                    .map_or(Datestamp::MAX, |undone| undone.max(before));
+                Box::new(
+                    schedule
+                        .previous(start, end)
+                        .filter_map(move |date| shift.apply(date))
+                        .dedup()
+                        .skip_while(move |date| date >= &before)
+                        .take_while(since),
+                )
+            }
+            Schedule::Roll(schedule, convention, calendar) => {
+                // Dates in the run of days off from `before` may roll back before it
+                let end = before
+                    .iter_days()
+                    .take_while(|date| !calendar.is_working_day(*date))
+                    .last()
+                    .and_then(|date| date.succ_opt())
+                    .unwrap_or(before);
+                Box::new(
+                    schedule
+                        .previous(days_off_before(calendar, from), end)
+                        .filter_map(move |date| calendar.roll(date, *convention))
+                        .dedup()
+                        .skip_while(move |date| date >= &before)
+                        .take_while(since),
+                )
+            }
+            Schedule::Take {
+                count,
+                anchor,
+                schedule,
+            } => {
+                let dates = schedule
+                    .upcoming_until(*anchor, before)
+                    .take(*count as usize)
+                    .take_while(|date| date < &before)
+                    .collect::<Vec<_>>();
+                Box::new(dates.into_iter().rev().take_while(since))
+            }
+            Schedule::Skip {
+                count,
+                anchor,
+                schedule,
+            } => {
+                match schedule
+                    .upcoming_until(*anchor, before)
+                    .nth(*count as usize)
+                {
+                    Some(first) => Box::new(
+                        schedule
+                            .previous(*anchor, before)
+                            .take_while(move |date| date >= &first.max(from)),
+                    ),
+                    None => Box::new(std::iter::empty()),
+                }
+            }
+            Schedule::TimeFunctionNot(schedule) => {
+                let mut previous = schedule.previous(from, before).peekable();
+                let mut all = days().peekable();
+                Box::new(std::iter::from_fn(move || loop {
+                    let day = *all.peek()?;
+                    match previous.peek() {
+                        Some(date) if date > &day => {
+                            previous.next();
+                        }
+                        Some(date) if date == &day => {
+                            previous.next();
+                            all.next();
+                        }
+                        _ => return all.next(),
+                    }
+                }))
+            }
+            Schedule::TimeFunctionOr(schedule1, schedule2) => Box::new(
+                schedule1
+                    .previous(from, before)
+                    .merge_by(schedule2.previous(from, before), |a, b| a > b),
+            ),
+            Schedule::TimeFunctionAnd(schedule1, schedule2) => Box::new(
+                schedule1
+                    .previous(from, before)
+                    .map(std::cmp::Reverse)
+                    .same(schedule2.previous(from, before).map(std::cmp::Reverse))
+                    .map(|date| date.0),
+            ),
+            Schedule::TimeFunctionBy(..) => {
+                let dates = self.between(from, before).collect::<Vec<_>>();
+                Box::new(dates.into_iter().rev())
+            }
+            Schedule::TimeFunctionBefore(schedule, predicate) => {
+                match predicate.upcoming_until(from, before).next() {
+                    Some(end) => schedule.previous(from, before.min(end)),
+                    None => Box::new(std::iter::empty()),
+                }
+            }
+            Schedule::TimeFunctionAfter(schedule, predicate) => {
+                match predicate.upcoming_until(from, before).next() {
+                    Some(start) => Box::new(
+                        schedule
+                            .previous(from, before)
+                            .take_while(move |date| date >= &start),
+                    ),
+                    None => Box::new(std::iter::empty()),
+                }
+            }
+        }
+    }
+
+    /// The date a monthly schedule falls on in the month with the [`month_index`].
+    fn day_in_month(&self, index: i32) -> Option<Datestamp> {
+        let (year, month) = (index.div_euclid(12), index.rem_euclid(12) as u32 + 1);
+        match self {
+            Schedule::MonthDay(nth) => calendar::nth_day(year, month, *nth, |_| true),
+            Schedule::Weekday { weekday, nth } => {
+                calendar::nth_day(year, month, *nth, |date| date.weekday() == *weekday)
+            }
+            Schedule::WorkDay(nth, calendar) => {
+                calendar::nth_day(year, month, *nth, |date| calendar.is_working_day(date))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Constructors for building schedules in code, as the compiler builds them from source.
+impl Schedule {
+    /// Day of month, month, day of week and an optional year, as in the source.
+    pub fn cron(fields: &str) -> Result<Self, cron::error::Error> {
+        // Dates are taken at noon, which keeps them clear of any change of timezone
+        std::str::FromStr::from_str(&format!("0 0 12 {fields}")).map(Self::Cron)
+    }
+
+    pub fn date(date: Datestamp) -> Self {
+        Self::Date(date)
+    }
+
+    pub fn work(calendar: Calendar) -> Self {
+        Self::Work(calendar)
+    }
+
+    pub fn holidays(calendar: Calendar) -> Self {
+        Self::Holidays(calendar)
+    }
+
+    pub fn every(step: Period, anchor: Datestamp) -> Self {
+        Self::Every { anchor, step }
+    }
+
+    pub fn month_day(nth: i8) -> Self {
+        Self::MonthDay(nth)
+    }
+
+    pub fn weekday(nth: i8, weekday: Weekday) -> Self {
+        Self::Weekday { weekday, nth }
+    }
+
+    pub fn work_day(nth: i8, calendar: Calendar) -> Self {
+        Self::WorkDay(nth, calendar)
+    }
+
+    /// Every day from `start` to `end`, each given as a month and a day.
+    pub fn season(start: (u32, u32), end: (u32, u32)) -> Self {
+        Self::Season { start, end }
+    }
+
+    pub fn offset(self, shift: Shift) -> Self {
+        Self::Offset(Box::new(self), shift)
+    }
+
+    pub fn roll(self, roll: Roll, calendar: Calendar) -> Self {
+        Self::Roll(Box::new(self), roll, calendar)
+    }
+
+    /// The first `count` dates on or after `anchor`.
+    pub fn take(self, count: u32, anchor: Datestamp) -> Self {
+        Self::Take {
+            count,
+            anchor,
+            schedule: Box::new(self),
+        }
+    }
+
+    /// The dates on or after `anchor`, but for the first `count` of them.
+    pub fn skip(self, count: u32, anchor: Datestamp) -> Self {
+        Self::Skip {
+            count,
+            anchor,
+            schedule: Box::new(self),
+        }
+    }
+
+    pub fn and(self, other: Self) -> Self {
+        Self::TimeFunctionAnd(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: Self) -> Self {
+        Self::TimeFunctionOr(Box::new(self), Box::new(other))
+    }
+
+    pub fn by(self, until: Self) -> Self {
+        Self::TimeFunctionBy(Box::new(self), Box::new(until))
+    }
+
+    pub fn before(self, other: Self) -> Self {
+        Self::TimeFunctionBefore(Box::new(self), Box::new(other))
+    }
+
+    pub fn after(self, other: Self) -> Self {
+        Self::TimeFunctionAfter(Box::new(self), Box::new(other))
+    }
+}
+
+impl std::ops::Not for Schedule {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self::TimeFunctionNot(Box::new(self))
+    }
+}
+
+/// Written in parentheses as Mercury syntax, which compiles back to the same schedule given the
+/// same calendars and holidays.
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Cron(cron) => {
+                // The time of day is added when the schedule is compiled
+                let source = cron.source();
+                write!(f, "({})", source.strip_prefix("0 0 12 ").unwrap_or(source))
+            }
+            Schedule::Date(date) => write!(f, "({date})"),
+            Schedule::Work(calendar) => {
+                let days = calendar
+                    .own_holidays()
+                    .map(|date| date.to_string())
+                    .chain(calendar.names().iter().cloned())
+                    .collect::<Vec<_>>();
+                if days.is_empty() {
+                    write!(f, "(work)")
+                } else {
+                    write!(f, "(work({}))", days.join(", "))
+                }
+            }
+            Schedule::Holidays(calendar) => {
+                write!(f, "(holidays({}))", calendar.names().join(", "))
+            }
+            Schedule::Every { anchor, step } => write!(f, "(every({step}; {anchor}))"),
+            Schedule::MonthDay(nth) => write!(f, "({} day)", ordinal(*nth)),
+            Schedule::Weekday { weekday, nth } => {
+                let name = match weekday {
+                    Weekday::Mon => "monday",
+                    Weekday::Tue => "tuesday",
+                    Weekday::Wed => "wednesday",
+                    Weekday::Thu => "thursday",
+                    Weekday::Fri => "friday",
+                    Weekday::Sat => "saturday",
+                    Weekday::Sun => "sunday",
+                };
+                write!(f, "({} {name})", ordinal(*nth))
+            }
+            Schedule::WorkDay(nth, calendar) => {
+                write!(f, "({} workday", ordinal(*nth))?;
+                if !calendar.names().is_empty() {
+                    write!(f, "({})", calendar.names().join(", "))?;
+                }
+                write!(f, ")")
+            }
+            Schedule::Season {
+                start: (start_month, start_day),
+                end: (end_month, end_day),
+            } => write!(
+                f,
+                "(season({start_month:02}-{start_day:02}..{end_month:02}-{end_day:02}))"
+            ),
+            Schedule::Offset(schedule, shift) => write!(f, "({schedule} {shift})"),
+            Schedule::Roll(schedule, convention, calendar) => {
+                write!(f, "(roll({convention}; {schedule}")?;
+                if !calendar.names().is_empty() {
+                    write!(f, "; {}", calendar.names().join(", "))?;
+                }
+                write!(f, "))")
+            }
+            Schedule::Take {
+                count,
+                anchor,
+                schedule,
+            } => write!(f, "(take({count}; {schedule}; {anchor}))"),
+            Schedule::Skip {
+                count,
+                anchor,
+                schedule,
+            } => write!(f, "(skip({count}; {schedule}; {anchor}))"),
+            Schedule::TimeFunctionNot(schedule) => write!(f, "(!{schedule})"),
+            Schedule::TimeFunctionOr(schedule1, schedule2) => {
+                write!(f, "({schedule1} | {schedule2})")
+            }
+            Schedule::TimeFunctionAnd(schedule1, schedule2) => {
+                write!(f, "({schedule1} & {schedule2})")
+            }
+            Schedule::TimeFunctionBy(schedule_predicate, schedule_next) => {
+                write!(f, "(by({schedule_predicate}; {schedule_next}))")
+            }
+            Schedule::TimeFunctionBefore(schedule, predicate) => {
+                write!(f, "({schedule} < {predicate})")
+            }
+            Schedule::TimeFunctionAfter(schedule, predicate) => {
+                write!(f, "({schedule} > {predicate})")
+            }
+        }
+    }
+}
+
+/// An ordinal such as `second last`, for positions counted from the end when negative.
+fn ordinal(nth: i8) -> String {
+    const WORDS: [&str; 5] = ["first", "second", "third", "fourth", "fifth"];
+    let word = match WORDS.get(usize::from(nth.unsigned_abs()).wrapping_sub(1)) {
+        Some(word) => word.to_string(),
+        None => format!("{}th", nth.unsigned_abs()),
+    };
+    match nth {
+        -1 => "last".into(),
+        ..0 => format!("{word} last"),
+        _ => word,
+    }
+}
+
+/// The number of months from the start of year 0 to the month of `date`.
+fn month_index(date: Datestamp) -> i32 {
+    date.year() * 12 + date.month0() as i32
+}
+
+/// The first day of the run of days off just before `date`, or `date` itself after a working day.
+fn days_off_before(calendar: &Calendar, date: Datestamp) -> Datestamp {
+    date.iter_days()
+        .rev()
+        .skip(1)
+        .take_while(|date| !calendar.is_working_day(*date))
+        .last()
+        .unwrap_or(date)
+}
+
+/// Cron schedules are evaluated at midday, clear of any change of day.
+fn noon(date: Datestamp) -> chrono::DateTime<chrono::Utc> {
+    date.and_time(chrono::NaiveTime::from_hms_opt(12, 0, 0).unwrap())
+        .and_utc()
 }
 
 trait Same<T>: Iterator<Item = T> {
@@ -112,8 +864,10 @@ trait Same<T>: Iterator<Item = T> {
     {
         let mut other = other.into_iter().peekable();
         std::iter::from_fn(move || {
-            while let Some(item_self) = self.next() {
-                while let Some(item_other) = other.peek() {
+            for item_self in self.by_ref() {
+                // Nothing more can be shared once either side ends
+                loop {
+                    let item_other = other.peek()?;
                     if &item_self == item_other {
                         return other.next();
                     } else if &item_self < item_other {
@@ -135,12 +889,13 @@ where
 {
 }
 
-pub(crate) fn event_queue<'a: 'b, 'b, T: 'a>(
-    events: impl Iterator<Item = (&'b Schedule, &'a T)>,
+pub(crate) fn event_queue<'b, T: Copy + 'b>(
+    events: impl Iterator<Item = (&'b Schedule, T)>,
     from: Datestamp,
-) -> impl Iterator<Item = (Datestamp, &'a T)> + 'b {
+    to: Datestamp,
+) -> impl Iterator<Item = (Datestamp, T)> + 'b {
     events
-        .map(move |event| event.0.upcoming(from).map(move |s| (s, event.1)))
+        .map(move |event| event.0.between(from, to).map(move |s| (s, event.1)))
         .kmerge_by(|a, b| a.0 < b.0)
 }
 
@@ -148,6 +903,8 @@ pub(crate) fn event_queue<'a: 'b, 'b, T: 'a>(
 mod test {
     use std::str::FromStr;
 
+    use chrono::Datelike;
+
     use super::*;
     fn s_cron(day: &str, month: &str) -> Schedule {
         Schedule::Cron(cron::Schedule::from_str(&format!("0 0 12 {} {} *", day, month)).unwrap())
@@ -164,6 +921,26 @@ mod test {
         date("2024-01-01")
     }
 
+    #[test]
+    fn period_month_end() {
+        assert_eq!(
+            Period::Months(1).after(date("2025-01-31")),
+            Some(date("2025-02-28"))
+        );
+        assert_eq!(
+            Period::Months(1).before(date("2024-03-31")),
+            Some(date("2024-02-29"))
+        );
+        assert_eq!(
+            Period::Years(1).after(date("2024-02-29")),
+            Some(date("2025-02-28"))
+        );
+        assert_eq!(
+            Period::Weeks(2).before(date("2024-01-10")),
+            Some(date("2023-12-27"))
+        );
+    }
+
     #[test]
     fn single_cron() {
         let schedule = s_cron("3", "*");
@@ -229,6 +1006,58 @@ mod test {
         assert_eq!(upcoming.next().unwrap(), date("2024-04-03"));
     }
 
+    #[test]
+    fn horizon() {
+        let never =
+            Schedule::TimeFunctionAnd(Box::new(s_cron("3", "*")), Box::new(s_cron("4", "*")));
+        assert_eq!(never.between(start(), date("2030-01-01")).next(), None);
+
+        let everyday = Schedule::Every {
+            anchor: start(),
+            step: Period::Days(1),
+        };
+        assert_eq!(
+            Schedule::TimeFunctionNot(Box::new(everyday))
+                .between(start(), date("2030-01-01"))
+                .next(),
+            None
+        );
+
+        let schedule = Schedule::TimeFunctionBy(Box::new(never), Box::new(s_cron("1", "*")));
+        assert_eq!(schedule.between(start(), date("2030-01-01")).next(), None);
+        assert!(!schedule.contains(start(), date("2024-01-03")));
+        assert_eq!(schedule.previous(start(), date("2030-01-01")).next(), None);
+
+        // Searching stops once either side of an intersection ends
+        let once =
+            Schedule::TimeFunctionAnd(Box::new(s_date("2024-01-03")), Box::new(s_cron("3", "*")));
+        let mut upcoming = once.upcoming(start());
+        assert_eq!(upcoming.next(), Some(date("2024-01-03")));
+        assert_eq!(upcoming.next(), None);
+        let once =
+            Schedule::TimeFunctionAnd(Box::new(s_cron("3", "*")), Box::new(s_date("2024-01-03")));
+        assert_eq!(once.upcoming(start()).nth(1), None);
+    }
+
+    #[test]
+    fn not_finite() {
+        let schedule = Schedule::TimeFunctionNot(Box::new(s_date("2024-01-02")));
+        let upcoming = schedule.between(start(), date("2024-01-05"));
+        assert_eq!(
+            upcoming.collect::<Vec<_>>(),
+            vec![date("2024-01-01"), date("2024-01-03"), date("2024-01-04")]
+        );
+    }
+
+    #[test]
+    fn by_last_date() {
+        let schedule =
+            Schedule::TimeFunctionBy(Box::new(s_date("2024-01-10")), Box::new(s_cron("1", "*")));
+        let mut upcoming = schedule.upcoming(start());
+        assert_eq!(upcoming.next(), Some(date("2024-01-10")));
+        assert_eq!(upcoming.next(), None);
+    }
+
     #[test]
     fn single_before() {
         let schedule = Schedule::TimeFunctionBefore(
@@ -252,15 +1081,619 @@ mod test {
         assert_eq!(upcoming.next().unwrap(), date("2024-06-03"));
     }
 
+    #[test]
+    fn recurring_before_after() {
+        // The first June from where the query starts, whichever year that is
+        let before = s_cron("3", "*").before(s_cron("1", "6"));
+        let after = s_cron("3", "*").after(s_cron("1", "6"));
+        let to = date("2025-01-01");
+        assert_eq!(
+            before.between(start(), to).collect::<Vec<_>>(),
+            [
+                "2024-01-03",
+                "2024-02-03",
+                "2024-03-03",
+                "2024-04-03",
+                "2024-05-03"
+            ]
+            .map(date)
+        );
+        assert_eq!(
+            before.between(date("2024-07-01"), to).collect::<Vec<_>>(),
+            [
+                "2024-07-03",
+                "2024-08-03",
+                "2024-09-03",
+                "2024-10-03",
+                "2024-11-03",
+                "2024-12-03"
+            ]
+            .map(date)
+        );
+        assert_eq!(
+            after.between(start(), to).collect::<Vec<_>>(),
+            [
+                "2024-06-03",
+                "2024-07-03",
+                "2024-08-03",
+                "2024-09-03",
+                "2024-10-03",
+                "2024-11-03",
+                "2024-12-03"
+            ]
+            .map(date)
+        );
+        assert_eq!(
+            after.upcoming(date("2024-07-01")).next(),
+            Some(date("2025-06-03"))
+        );
+        for (schedule, from) in [
+            (&before, start()),
+            (&after, start()),
+            (&after, date("2024-07-01")),
+        ] {
+            let dates = schedule.between(from, to).collect::<Vec<_>>();
+            let contained = from
+                .iter_days()
+                .take_while(|day| day < &to)
+                .filter(|day| schedule.contains(from, *day))
+                .collect::<Vec<_>>();
+            let mut previous = schedule.previous(from, to).collect::<Vec<_>>();
+            previous.reverse();
+            assert_eq!(contained, dates, "{schedule} from {from}");
+            assert_eq!(previous, dates, "{schedule} from {from}");
+        }
+
+        let never = s_cron("3", "*").before(s_date("2020-01-01"));
+        assert_eq!(never.upcoming(start()).next(), None);
+        assert!(!never.contains(start(), date("2024-01-03")));
+        assert_eq!(never.previous(start(), to).next(), None);
+    }
+
+    #[test]
+    fn single_work() {
+        let schedule = Schedule::Work(Calendar::new([date("2024-01-08")]));
+        let mut upcoming = schedule.upcoming(date("2024-01-04"));
+        assert_eq!(upcoming.next().unwrap(), date("2024-01-04"));
+        assert_eq!(upcoming.next().unwrap(), date("2024-01-05"));
+        assert_eq!(upcoming.next().unwrap(), date("2024-01-09"));
+    }
+
+    #[test]
+    fn last_work_day_of_month() {
+        let schedule = Schedule::TimeFunctionBy(
+            Box::new(Schedule::Work(Calendar::new([date("2024-05-31")]))),
+            Box::new(s_cron("1", "*")),
+        );
+        let mut upcoming = schedule.upcoming(date("2024-03-01"));
+        assert_eq!(upcoming.next().unwrap(), date("2024-03-29"));
+        assert_eq!(upcoming.next().unwrap(), date("2024-04-30"));
+        assert_eq!(upcoming.next().unwrap(), date("2024-05-30"));
+        assert_eq!(upcoming.next().unwrap(), date("2024-06-28"));
+    }
+
+    #[test]
+    fn month_days() {
+        let dates = |schedule: Schedule| {
+            schedule
+                .between(date("2024-01-01"), date("2024-05-01"))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            dates(Schedule::MonthDay(-1)),
+            [
+                date("2024-01-31"),
+                date("2024-02-29"),
+                date("2024-03-31"),
+                date("2024-04-30")
+            ]
+        );
+        assert_eq!(
+            dates(Schedule::Weekday {
+                weekday: Weekday::Fri,
+                nth: -1
+            }),
+            [
+                date("2024-01-26"),
+                date("2024-02-23"),
+                date("2024-03-29"),
+                date("2024-04-26")
+            ]
+        );
+        assert_eq!(
+            dates(Schedule::Weekday {
+                weekday: Weekday::Mon,
+                nth: 5
+            }),
+            [date("2024-01-29"), date("2024-04-29")]
+        );
+        assert_eq!(
+            dates(Schedule::WorkDay(-1, Calendar::new([date("2024-01-31")]))),
+            [
+                date("2024-01-30"),
+                date("2024-02-29"),
+                date("2024-03-29"),
+                date("2024-04-30")
+            ]
+        );
+        assert_eq!(
+            dates(Schedule::WorkDay(1, uk())),
+            [
+                date("2024-01-02"),
+                date("2024-02-01"),
+                date("2024-03-01"),
+                date("2024-04-02")
+            ]
+        );
+    }
+
+    #[test]
+    fn season() {
+        let winter = Schedule::Season {
+            start: (12, 1),
+            end: (1, 15),
+        };
+        let dates = winter
+            .between(date("2023-11-01"), date("2024-03-01"))
+            .collect::<Vec<_>>();
+        assert_eq!(dates.len(), 46);
+        assert_eq!(dates.first(), Some(&date("2023-12-01")));
+        assert_eq!(dates.last(), Some(&date("2024-01-15")));
+
+        let schedule = Schedule::TimeFunctionAnd(Box::new(s_cron("10", "*")), Box::new(winter));
+        let upcoming = schedule
+            .between(date("2023-01-01"), date("2024-12-31"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            upcoming,
+            [
+                date("2023-01-10"),
+                date("2023-12-10"),
+                date("2024-01-10"),
+                date("2024-12-10")
+            ]
+        );
+    }
+
+    #[test]
+    fn offset() {
+        let schedule = Schedule::Offset(Box::new(s_cron("5", "*")), Shift::Later(Period::Days(21)));
+        let mut upcoming = schedule.upcoming(date("2024-02-10"));
+        assert_eq!(upcoming.next(), Some(date("2024-02-26")));
+        assert_eq!(upcoming.next(), Some(date("2024-03-26")));
+
+        // Both 30 and 31 January land on 29 February
+        let schedule = Schedule::Offset(
+            Box::new(s_cron("30,31", "*")),
+            Shift::Later(Period::Months(1)),
+        );
+        let mut upcoming = schedule.upcoming(date("2024-02-01"));
+        assert_eq!(upcoming.next(), Some(date("2024-02-29")));
+        assert_eq!(upcoming.next(), Some(date("2024-04-30")));
+
+        let schedule =
+            Schedule::Offset(Box::new(s_cron("1", "*")), Shift::Earlier(Period::Days(1)));
+        let mut upcoming = schedule.upcoming(date("2024-02-10"));
+        assert_eq!(upcoming.next(), Some(date("2024-02-29")));
+        assert_eq!(upcoming.next(), Some(date("2024-03-31")));
+    }
+
+    fn s_every(anchor: &str, step: Period) -> Schedule {
+        Schedule::Every {
+            anchor: date(anchor),
+            step,
+        }
+    }
+
+    #[test]
+    fn single_every() {
+        let schedule = s_every("2023-12-22", Period::Weeks(2));
+        let mut upcoming = schedule.upcoming(start());
+        assert_eq!(upcoming.next().unwrap(), date("2024-01-05"));
+        assert_eq!(upcoming.next().unwrap(), date("2024-01-19"));
+
+        // Nothing before the anchor, but the anchor itself is included
+        let schedule = s_every("2024-01-05", Period::Days(4));
+        let mut upcoming = schedule.upcoming(start());
+        assert_eq!(upcoming.next().unwrap(), date("2024-01-05"));
+        assert_eq!(upcoming.next().unwrap(), date("2024-01-09"));
+    }
+
+    #[test]
+    fn every_month_end() {
+        let schedule = s_every("2023-01-31", Period::Months(1));
+        let upcoming = schedule
+            .upcoming(date("2024-01-31"))
+            .take(4)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            upcoming,
+            [
+                date("2024-01-31"),
+                date("2024-02-29"),
+                date("2024-03-31"),
+                date("2024-04-30")
+            ]
+        );
+
+        let schedule = s_every("2020-02-29", Period::Years(1));
+        let mut upcoming = schedule.upcoming(date("2023-03-01"));
+        assert_eq!(upcoming.next().unwrap(), date("2024-02-29"));
+        assert_eq!(upcoming.next().unwrap(), date("2025-02-28"));
+    }
+
+    #[test]
+    fn every_composed() {
+        // Every four weeks, except in December
+        let schedule = Schedule::TimeFunctionAnd(
+            Box::new(s_every("2024-10-04", Period::Weeks(4))),
+            Box::new(Schedule::TimeFunctionNot(Box::new(s_cron("*", "12")))),
+        );
+        let mut upcoming = schedule.upcoming(date("2024-10-01"));
+        assert_eq!(upcoming.next().unwrap(), date("2024-10-04"));
+        assert_eq!(upcoming.next().unwrap(), date("2024-11-01"));
+        assert_eq!(upcoming.next().unwrap(), date("2024-11-29"));
+        assert_eq!(upcoming.next().unwrap(), date("2025-01-24"));
+
+        let schedules = [
+            (s_every("2024-01-05", Period::Weeks(2)), "fortnightly"),
+            (s_cron("10", "*"), "monthly"),
+        ];
+        let events = event_queue(
+            schedules.iter().map(|e| (&e.0, &e.1)),
+            start(),
+            Datestamp::MAX,
+        )
+        .take(4)
+        .map(|(date, name)| (date, *name))
+        .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                (date("2024-01-05"), "fortnightly"),
+                (date("2024-01-10"), "monthly"),
+                (date("2024-01-19"), "fortnightly"),
+                (date("2024-02-02"), "fortnightly"),
+            ]
+        );
+    }
+
+    #[test]
+    fn roll_conventions() {
+        let easter = Calendar::new([date("2024-03-29"), date("2024-04-01")]);
+        let roll = |day, convention| easter.roll(date(day), convention).unwrap();
+        assert_eq!(roll("2024-03-28", Roll::Following), date("2024-03-28"));
+        assert_eq!(roll("2024-03-30", Roll::Following), date("2024-04-02"));
+        assert_eq!(roll("2024-03-30", Roll::Preceding), date("2024-03-28"));
+        assert_eq!(
+            roll("2024-03-30", Roll::ModifiedFollowing),
+            date("2024-03-28")
+        );
+        assert_eq!(
+            roll("2024-04-01", Roll::ModifiedFollowing),
+            date("2024-04-02")
+        );
+        assert_eq!(
+            roll("2024-06-01", Roll::ModifiedPreceding),
+            date("2024-06-03")
+        );
+        assert_eq!(
+            roll("2024-06-08", Roll::ModifiedPreceding),
+            date("2024-06-07")
+        );
+    }
+
+    #[test]
+    fn roll_sorted() {
+        // Saturday and Sunday both roll onto Monday, from before the start of the range
+        let schedule = Schedule::Roll(
+            Box::new(Schedule::TimeFunctionOr(
+                Box::new(Schedule::TimeFunctionOr(
+                    Box::new(s_date("2024-01-06")),
+                    Box::new(s_date("2024-01-07")),
+                )),
+                Box::new(s_date("2024-01-09")),
+            )),
+            Roll::Following,
+            Calendar::default(),
+        );
+        let upcoming = schedule.upcoming(date("2024-01-08")).collect::<Vec<_>>();
+        assert_eq!(upcoming, [date("2024-01-08"), date("2024-01-09")]);
+
+        let schedule = Schedule::Roll(
+            Box::new(s_cron("28", "*")),
+            Roll::Preceding,
+            Calendar::default(),
+        );
+        let mut upcoming = schedule.upcoming(start());
+        assert_eq!(upcoming.next().unwrap(), date("2024-01-26"));
+        assert_eq!(upcoming.next().unwrap(), date("2024-02-28"));
+        assert_eq!(upcoming.next().unwrap(), date("2024-03-28"));
+        assert_eq!(upcoming.next().unwrap(), date("2024-04-26"));
+    }
+
+    fn uk() -> Calendar {
+        let mut calendar = Calendar::default();
+        for rule in [
+            Holiday::Fixed {
+                month: 1,
+                day: 1,
+                observed: true,
+            },
+            Holiday::Easter(-2),
+            Holiday::Easter(1),
+            Holiday::Nth {
+                month: 5,
+                weekday: chrono::Weekday::Mon,
+                nth: 1,
+            },
+            Holiday::Nth {
+                month: 5,
+                weekday: chrono::Weekday::Mon,
+                nth: -1,
+            },
+            Holiday::Nth {
+                month: 8,
+                weekday: chrono::Weekday::Mon,
+                nth: -1,
+            },
+            Holiday::Fixed {
+                month: 12,
+                day: 25,
+                observed: true,
+            },
+            Holiday::Fixed {
+                month: 12,
+                day: 26,
+                observed: true,
+            },
+        ] {
+            calendar.push(rule);
+        }
+        calendar
+    }
+
+    #[test]
+    fn easter_sunday() {
+        assert_eq!(easter(2019), Some(date("2019-04-21")));
+        assert_eq!(easter(2024), Some(date("2024-03-31")));
+        assert_eq!(easter(2025), Some(date("2025-04-20")));
+        assert_eq!(easter(2038), Some(date("2038-04-25")));
+    }
+
+    #[test]
+    fn holiday_rules() {
+        let schedule = Schedule::Holidays(uk());
+        let upcoming = schedule
+            .upcoming(date("2022-01-01"))
+            .take(9)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            upcoming,
+            [
+                date("2022-01-03"),
+                date("2022-04-15"),
+                date("2022-04-18"),
+                date("2022-05-02"),
+                date("2022-05-30"),
+                date("2022-08-29"),
+                date("2022-12-26"),
+                date("2022-12-27"),
+                date("2023-01-02"),
+            ]
+        );
+
+        // Christmas on a Saturday is observed on the Monday, and Boxing Day on the Tuesday
+        let mut calendar = uk();
+        assert!(!calendar.is_working_day(date("2021-12-27")));
+        assert!(!calendar.is_working_day(date("2021-12-28")));
+        assert!(calendar.is_working_day(date("2021-12-29")));
+
+        // Holidays worked out before a rule is added are worked out again
+        let rule = Holiday::Fixed {
+            month: 12,
+            day: 29,
+            observed: false,
+        };
+        calendar.push(rule);
+        assert!(!calendar.is_working_day(date("2021-12-29")));
+        let mut fresh = uk();
+        fresh.push(rule);
+        assert_eq!(fresh, calendar);
+
+        let schedule = Schedule::Roll(Box::new(s_cron("25", "12")), Roll::Following, uk());
+        assert_eq!(schedule.upcoming(start()).next(), Some(date("2024-12-27")));
+    }
+
+    #[test]
+    fn take_skip() {
+        // Twelve payments from March, with the projection starting in May
+        let loan = Schedule::TimeFunctionAfter(
+            Box::new(s_cron("15", "*")),
+            Box::new(s_date("2024-03-01")),
+        );
+        let schedule = loan.clone().take(12, start());
+        let upcoming = schedule.upcoming(date("2024-05-01")).collect::<Vec<_>>();
+        assert_eq!(upcoming.len(), 10);
+        assert_eq!(upcoming[0], date("2024-05-15"));
+        assert_eq!(upcoming[9], date("2025-02-15"));
+
+        let schedule = loan.skip(12, start());
+        let mut upcoming = schedule.upcoming(date("2024-05-01"));
+        assert_eq!(upcoming.next().unwrap(), date("2025-03-15"));
+        assert_eq!(upcoming.next().unwrap(), date("2025-04-15"));
+
+        let schedule = s_every("2024-01-05", Period::Weeks(2)).take(2, start());
+        assert_eq!(schedule.upcoming(date("2024-02-01")).next(), None);
+
+        // Counted from the anchor, not from where the query starts
+        let schedule = s_cron("15", "*").take(12, date("2024-03-01"));
+        let upcoming = schedule.upcoming(EPOCH).collect::<Vec<_>>();
+        assert_eq!(upcoming.len(), 12);
+        assert_eq!(upcoming[0], date("2024-03-15"));
+        assert_eq!(upcoming[11], date("2025-02-15"));
+        assert_eq!(
+            schedule
+                .between(date("2024-06-01"), date("2024-08-01"))
+                .collect::<Vec<_>>(),
+            ["2024-06-15", "2024-07-15"].map(date)
+        );
+        let schedule = s_cron("15", "*").skip(12, date("2024-03-01"));
+        assert_eq!(schedule.upcoming(EPOCH).next(), Some(date("2025-03-15")));
+        assert!(!schedule.contains(EPOCH, date("2024-03-15")));
+    }
+
+    /// Schedules that mean the same from 2024 as from the epoch.
+    fn queries() -> Vec<Schedule> {
+        let easter = Calendar::new([date("2024-03-29"), date("2024-04-01")]);
+        let after_march = |schedule| {
+            Schedule::TimeFunctionAfter(Box::new(schedule), Box::new(s_date("2024-03-05")))
+        };
+        vec![
+            s_cron("3", "*"),
+            s_date("2024-06-30"),
+            Schedule::Work(easter.clone()),
+            Schedule::Holidays(uk()),
+            s_every("2023-12-22", Period::Weeks(2)),
+            s_every("2024-01-31", Period::Months(1)),
+            Schedule::MonthDay(-1),
+            Schedule::Weekday {
+                weekday: Weekday::Fri,
+                nth: -2,
+            },
+            Schedule::WorkDay(-1, easter.clone()),
+            Schedule::Season {
+                start: (12, 1),
+                end: (1, 15),
+            },
+            Schedule::Offset(Box::new(s_cron("5", "*")), Shift::Later(Period::Days(21))),
+            Schedule::Offset(Box::new(s_cron("31", "*")), Shift::Later(Period::Months(1))),
+            Schedule::Offset(
+                Box::new(s_every("2024-01-31", Period::Months(1))),
+                Shift::Earlier(Period::Weeks(1)),
+            ),
+            Schedule::Roll(
+                Box::new(s_cron("30", "*")),
+                Roll::ModifiedFollowing,
+                easter.clone(),
+            ),
+            Schedule::Roll(Box::new(s_cron("*", "*")), Roll::Preceding, easter.clone()),
+            after_march(s_cron("15", "*")).take(4, start()),
+            after_march(s_cron("15", "*")).skip(4, start()),
+            s_cron("15", "*").take(4, date("2024-02-01")),
+            s_cron("15", "*").skip(4, date("2024-02-01")),
+            Schedule::TimeFunctionNot(Box::new(s_cron("*", "1,3"))),
+            Schedule::TimeFunctionOr(Box::new(s_cron("3", "*")), Box::new(s_cron("5", "*"))),
+            Schedule::TimeFunctionAnd(Box::new(s_cron("3", "*")), Box::new(s_cron("*", "1/2"))),
+            Schedule::TimeFunctionBefore(
+                Box::new(s_cron("3", "*")),
+                Box::new(s_date("2024-03-05")),
+            ),
+            after_march(s_cron("3", "*")),
+            Schedule::TimeFunctionBy(Box::new(Schedule::Work(easter)), Box::new(s_cron("1", "*"))),
+        ]
+    }
+
+    #[test]
+    fn contains() {
+        for schedule in queries() {
+            let dates = schedule
+                .between(date("2024-01-01"), date("2025-01-01"))
+                .collect::<std::collections::BTreeSet<_>>();
+            for day in date("2024-01-01")
+                .iter_days()
+                .take_while(|day| day.year() == 2024)
+            {
+                assert_eq!(
+                    schedule.contains(date("2024-01-01"), day),
+                    dates.contains(&day),
+                    "{day} in {schedule:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn combinators_contain_between() {
+        let never = || s_cron("3", "*").and(s_cron("4", "*"));
+        let combinators = [
+            s_cron("3", "*").before(s_cron("10", "*")),
+            s_cron("3", "*").after(s_cron("10", "*")),
+            s_cron("3", "*").before(s_date("2020-01-01")),
+            s_cron("3", "*").after(s_date("2020-01-01")),
+            s_cron("3", "*").before(never()),
+            s_cron("3", "*").after(never()),
+            s_cron("3", "*").take(2, date("2024-02-10")),
+            s_cron("3", "*").skip(2, date("2024-02-10")),
+            Schedule::Work(uk()).by(s_cron("1", "*")),
+            s_cron("*/4", "*").by(s_cron("10", "*")),
+            !s_cron("*", "1/2"),
+            s_cron("3", "*").or(s_cron("10", "*")),
+            Schedule::Work(uk()).and(s_cron("*/2", "*")),
+        ];
+        let to = date("2024-07-01");
+        for schedule in combinators {
+            for from in ["2024-01-01", "2024-02-10", "2024-03-04"].map(date) {
+                let contained = from
+                    .iter_days()
+                    .take_while(|day| day < &to)
+                    .filter(|day| schedule.contains(from, *day))
+                    .collect::<Vec<_>>();
+                let mut previous = schedule.previous(from, to).collect::<Vec<_>>();
+                previous.reverse();
+                let between = schedule.between(from, to).collect::<Vec<_>>();
+                assert_eq!(between, contained, "{schedule} from {from}");
+                assert_eq!(between, previous, "{schedule} from {from}");
+            }
+        }
+    }
+
+    #[test]
+    fn previous() {
+        for schedule in queries() {
+            let dates = schedule
+                .between(date("2024-01-01"), date("2025-01-01"))
+                .collect::<Vec<_>>();
+            let mut previous = schedule
+                .previous(date("2024-01-01"), date("2025-01-01"))
+                .collect::<Vec<_>>();
+            previous.reverse();
+            assert_eq!(previous, dates, "{schedule:?}");
+        }
+
+        let schedule = s_cron("3", "*");
+        let mut previous = schedule.previous(EPOCH, date("2024-03-03"));
+        assert_eq!(previous.next(), Some(date("2024-02-03")));
+        assert_eq!(previous.next(), Some(date("2024-01-03")));
+        assert_eq!(
+            s_date("2023-12-31")
+                .previous(start(), date("2024-06-01"))
+                .next(),
+            None
+        );
+    }
+
+    #[test]
+    fn between() {
+        let schedule = s_every("2024-01-05", Period::Weeks(2));
+        let dates = schedule
+            .between(date("2024-01-05"), date("2024-02-16"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            dates,
+            [date("2024-01-05"), date("2024-01-19"), date("2024-02-02")]
+        );
+    }
+
     #[test]
     fn events_getter() {
-        let schedules = vec![
+        let schedules = [
             (s_cron("8", "*"), "third"),
             (s_cron("5", "*"), "second"),
             (s_cron("3", "*"), "first"),
         ];
 
-        let mut events = event_queue(schedules.iter().map(|e| (&e.0, e)), start());
+        let mut events = event_queue(schedules.iter().map(|e| (&e.0, e)), start(), Datestamp::MAX);
         let mut next;
 
         next = events.next().unwrap();
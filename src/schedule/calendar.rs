@@ -0,0 +1,341 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use chrono::{Datelike, Weekday};
+
+use crate::Datestamp;
+
+/// How to move a date that is not a working day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Roll {
+    /// The next working day
+    Following,
+    /// The previous working day
+    Preceding,
+    /// The next working day, unless that is in the next month, then the previous
+    ModifiedFollowing,
+    /// The previous working day, unless that is in the previous month, then the next
+    ModifiedPreceding,
+}
+
+impl std::fmt::Display for Roll {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Roll::Following => "following",
+            Roll::Preceding => "preceding",
+            Roll::ModifiedFollowing => "modified following",
+            Roll::ModifiedPreceding => "modified preceding",
+        })
+    }
+}
+
+/// A holiday that recurs every year.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Holiday {
+    /// The same day every year, moved to the next free weekday if `observed` and it falls on a
+    /// weekend
+    Fixed {
+        month: u32,
+        day: u32,
+        observed: bool,
+    },
+    /// The nth weekday of a month, counting from the end if negative
+    Nth {
+        month: u32,
+        weekday: Weekday,
+        nth: i8,
+    },
+    /// A number of days from Easter Sunday
+    Easter(i32),
+}
+
+impl Holiday {
+    /// The day the holiday falls on in `year`, before any weekend is observed.
+    fn date_in(&self, year: i32) -> Option<Datestamp> {
+        match *self {
+            Holiday::Fixed { month, day, .. } => Datestamp::from_ymd_opt(year, month, day),
+            Holiday::Nth {
+                month,
+                weekday,
+                nth,
+            } => nth_day(year, month, nth, |date| date.weekday() == weekday),
+            Holiday::Easter(days) => {
+                easter(year)?.checked_add_signed(chrono::Duration::days(days.into()))
+            }
+        }
+    }
+}
+
+/// The nth day of a month that is `wanted`, counting from the end if negative.
+pub(crate) fn nth_day(
+    year: i32,
+    month: u32,
+    nth: i8,
+    wanted: impl Fn(Datestamp) -> bool,
+) -> Option<Datestamp> {
+    let first = Datestamp::from_ymd_opt(year, month, 1)?;
+    let in_month = |date: &Datestamp| date.month() == month;
+    let index = usize::from(nth.unsigned_abs()).checked_sub(1)?;
+    if nth < 0 {
+        let last = first
+            .checked_add_months(chrono::Months::new(1))?
+            .pred_opt()?;
+        last.iter_days()
+            .rev()
+            .take_while(in_month)
+            .filter(|date| wanted(*date))
+            .nth(index)
+    } else {
+        first
+            .iter_days()
+            .take_while(in_month)
+            .filter(|date| wanted(*date))
+            .nth(index)
+    }
+}
+
+/// Easter Sunday in the Gregorian calendar.
+pub fn easter(year: i32) -> Option<Datestamp> {
+    let a = year % 19;
+    let (b, c) = (year / 100, year % 100);
+    let (d, e) = (b / 4, b % 4);
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let (i, k) = (c / 4, c % 4);
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+    Datestamp::from_ymd_opt(year, month as u32, day as u32)
+}
+
+fn is_weekend(date: Datestamp) -> bool {
+    matches!(date.weekday(), Weekday::Sat | Weekday::Sun)
+}
+
+/// The holidays the rules give each year, worked out the first time a year is asked for.
+#[derive(Default)]
+struct Observed(Mutex<HashMap<i32, Arc<BTreeSet<Datestamp>>>>);
+
+impl Observed {
+    fn get(
+        &self,
+        year: i32,
+        rules: impl FnOnce() -> BTreeSet<Datestamp>,
+    ) -> Arc<BTreeSet<Datestamp>> {
+        let mut years = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        years
+            .entry(year)
+            .or_insert_with(|| Arc::new(rules()))
+            .clone()
+    }
+
+    fn clear(&mut self) {
+        self.0
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
+
+impl Clone for Observed {
+    fn clone(&self) -> Self {
+        let years = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        Self(Mutex::new(years.clone()))
+    }
+}
+
+/// Only a cache, so it has no bearing on whether calendars are the same.
+impl PartialEq for Observed {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Observed {}
+
+impl std::fmt::Debug for Observed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("..")
+    }
+}
+
+/// A working week of Monday to Friday, less a set of holidays.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Calendar {
+    holidays: BTreeSet<Datestamp>,
+    rules: Vec<Holiday>,
+    /// The calendars included by name, in order
+    names: Vec<String>,
+    /// The one-off holidays that came from the named calendars
+    inherited: BTreeSet<Datestamp>,
+    observed: Observed,
+}
+
+impl Calendar {
+    pub fn new(holidays: impl IntoIterator<Item = Datestamp>) -> Self {
+        Self {
+            holidays: holidays.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Add a holiday that recurs every year.
+    pub fn push(&mut self, rule: Holiday) {
+        self.rules.push(rule);
+        self.observed.clear();
+    }
+
+    /// Add all the holidays of another calendar.
+    pub fn merge(&mut self, other: &Calendar) {
+        self.holidays.extend(other.holidays());
+        self.rules.extend(other.rules.iter().copied());
+        self.observed.clear();
+        self.names.extend(other.names.iter().cloned());
+        self.inherited.extend(other.inherited.iter().copied());
+    }
+
+    /// Add all the holidays of the calendar declared as `name`.
+    pub fn include(&mut self, name: &str, other: &Calendar) {
+        self.merge(other);
+        self.inherited.extend(other.holidays());
+        self.names.push(name.into());
+    }
+
+    /// The names of the calendars included, in order.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// The one-off holidays added directly, rather than by including a named calendar.
+    pub fn own_holidays(&self) -> impl Iterator<Item = Datestamp> + '_ {
+        self.holidays.difference(&self.inherited).copied()
+    }
+
+    /// The one-off holidays, not including those from rules.
+    pub fn holidays(&self) -> impl Iterator<Item = Datestamp> + '_ {
+        self.holidays.iter().copied()
+    }
+
+    pub fn rules(&self) -> &[Holiday] {
+        &self.rules
+    }
+
+    /// The holidays the rules give for `year`, some of which may be observed in the next year.
+    fn observed(&self, year: i32) -> Arc<BTreeSet<Datestamp>> {
+        self.observed.get(year, || self.observed_in(year))
+    }
+
+    fn observed_in(&self, year: i32) -> BTreeSet<Datestamp> {
+        let mut days = BTreeSet::new();
+        let mut weekends = Vec::new();
+        for rule in &self.rules {
+            match rule.date_in(year) {
+                Some(date)
+                    if matches!(rule, Holiday::Fixed { observed: true, .. })
+                        && is_weekend(date) =>
+                {
+                    weekends.push(date)
+                }
+                Some(date) => {
+                    days.insert(date);
+                }
+                None => {}
+            }
+        }
+        weekends.sort();
+        for date in weekends {
+            if let Some(date) = date
+                .iter_days()
+                .find(|date| !is_weekend(*date) && !days.contains(date))
+            {
+                days.insert(date);
+            }
+        }
+        days
+    }
+
+    /// Every holiday in `year`, in order.
+    fn year(&self, year: i32) -> impl Iterator<Item = Datestamp> {
+        let mut days = Datestamp::from_yo_opt(year, 1)
+            .map(|start| {
+                self.holidays
+                    .range(start..)
+                    .take_while(|date| date.year() == year)
+                    .copied()
+                    .collect::<BTreeSet<_>>()
+            })
+            .unwrap_or_default();
+        if !self.rules.is_empty() {
+            for rules_year in [year - 1, year] {
+                days.extend(
+                    self.observed(rules_year)
+                        .iter()
+                        .filter(|date| date.year() == year),
+                );
+            }
+        }
+        days.into_iter()
+    }
+
+    /// Every holiday on or after `from`, in order.
+    pub fn upcoming(&self, from: Datestamp) -> Box<dyn Iterator<Item = Datestamp> + '_> {
+        if self.rules.is_empty() {
+            return Box::new(self.holidays.range(from..).copied());
+        }
+        Box::new(
+            (from.year()..=Datestamp::MAX.year())
+                .flat_map(|year| self.year(year))
+                .skip_while(move |date| date < &from),
+        )
+    }
+
+    pub fn is_holiday(&self, date: Datestamp) -> bool {
+        self.holidays.contains(&date)
+            || (!self.rules.is_empty()
+                && (self.observed(date.year()).contains(&date)
+                    || self.observed(date.year() - 1).contains(&date)))
+    }
+
+    pub fn is_working_day(&self, date: Datestamp) -> bool {
+        !is_weekend(date) && !self.is_holiday(date)
+    }
+
+    /// The first working day on or after `date`.
+    pub fn following(&self, date: Datestamp) -> Option<Datestamp> {
+        date.iter_days().find(|date| self.is_working_day(*date))
+    }
+
+    /// The last working day on or before `date`.
+    pub fn preceding(&self, date: Datestamp) -> Option<Datestamp> {
+        date.iter_days()
+            .rev()
+            .find(|date| self.is_working_day(*date))
+    }
+
+    /// Move `date` to a working day by `convention`.
+    pub fn roll(&self, date: Datestamp, convention: Roll) -> Option<Datestamp> {
+        let same_month = |rolled: &Datestamp| rolled.month() == date.month();
+        match convention {
+            Roll::Following => self.following(date),
+            Roll::Preceding => self.preceding(date),
+            Roll::ModifiedFollowing => self
+                .following(date)
+                .filter(same_month)
+                .or_else(|| self.preceding(date)),
+            Roll::ModifiedPreceding => self
+                .preceding(date)
+                .filter(same_month)
+                .or_else(|| self.following(date)),
+        }
+    }
+}
+
+impl Extend<Datestamp> for Calendar {
+    fn extend<T: IntoIterator<Item = Datestamp>>(&mut self, iter: T) {
+        self.holidays.extend(iter)
+    }
+}
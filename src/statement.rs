@@ -2,12 +2,14 @@ use std::fmt::Debug;
 
 use crate::account;
 
-pub type Operation = Box<dyn Fn(&mut account::CtxMut) -> f64>;
+pub type Operation = Box<dyn Fn(&mut account::CtxMut) -> account::Money>;
 
 pub struct Statement {
     pub from: account::ID,
     pub to: account::ID,
     pub func: Operation,
+    /// The commodity `func` is measured in, otherwise that of `from`, or failing that `to`
+    pub commodity: Option<account::Commodity>,
     pub label: Option<String>,
 }
 
@@ -16,6 +18,7 @@ impl Debug for Statement {
         f.debug_struct("Statement")
             .field("from", &self.from)
             .field("to", &self.to)
+            .field("commodity", &self.commodity)
             .field("label", &self.label)
             .finish()
     }
@@ -27,3 +30,39 @@ pub enum Statements {
     Set(account::ID, Vec<Statements>),
     Single(Statement),
 }
+
+impl Statements {
+    /// Statements run in order on `account`, or on `self` without one, followed by moving
+    /// whatever is left in it to the account above.
+    pub(crate) fn list(
+        accounts: &mut account::Interner,
+        account: Option<account::ID>,
+        statements: Vec<Statements>,
+    ) -> Self {
+        let account = account.unwrap_or_else(|| accounts.get_or_intern_static("self"));
+        Self::List(account, Self::flushed(accounts, statements))
+    }
+
+    /// Statements run side by side on `account`, or on a `new` layer without one, followed by
+    /// moving whatever is left in it to the account above.
+    pub(crate) fn set(
+        accounts: &mut account::Interner,
+        account: Option<account::ID>,
+        statements: Vec<Statements>,
+    ) -> Self {
+        let account = account.unwrap_or_else(|| accounts.get_or_intern_static("new"));
+        Self::Set(account, Self::flushed(accounts, statements))
+    }
+
+    fn flushed(accounts: &mut account::Interner, mut statements: Vec<Statements>) -> Vec<Self> {
+        let sym_self = accounts.get_or_intern_static("self");
+        statements.push(Statements::Single(Statement {
+            from: sym_self,
+            func: Box::new(move |ctx| ctx[sym_self]),
+            to: accounts.get_or_intern_static("super"),
+            commodity: None,
+            label: None,
+        }));
+        statements
+    }
+}
@@ -1,28 +1,90 @@
-use std::str::FromStr;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
 
-use crate::{account, Datestamp, Event, Operation, Statement, Statements};
+use crate::{
+    account,
+    expression::{Expression, Function},
+    Datestamp, Decimal, Event, Operation, Statement, Statements,
+};
 
-use super::Schedule;
+use super::{
+    schedule::{Calendar, Holiday, Period, Roll, Shift},
+    Schedule,
+};
 use parser::Rule;
 
 use self::parser::{List, Node, NodeParent};
 
+mod error;
 mod parser;
+mod resolve;
+
+pub use error::{CompileError, ErrorKind, Location, Result, Span};
+pub use resolve::{normalise, Filesystem, Resolver};
 
 #[derive(Debug, PartialEq)]
 pub struct Context<'a> {
     pub accounts: &'a mut account::Interner,
+    /// Opening balances and other facts declared about accounts
+    pub registry: &'a mut account::Registry,
     pub date_start: Datestamp,
     pub date_end: Datestamp,
+    /// Holidays excluded from the `work` schedule
+    pub holidays: Calendar,
+    /// Values bound by `let`, visible to every file in the compilation
+    pub variables: HashMap<String, Value>,
+    /// Holiday calendars declared by name
+    pub calendars: HashMap<String, Calendar>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(
+        accounts: &'a mut account::Interner,
+        registry: &'a mut account::Registry,
+        date_start: Datestamp,
+        date_end: Datestamp,
+    ) -> Self {
+        Self {
+            accounts,
+            registry,
+            date_start,
+            date_end,
+            holidays: Default::default(),
+            variables: Default::default(),
+            calendars: Default::default(),
+        }
+    }
+}
+
+/// A value bound to a name by `let`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Amount(Expression),
+    Date(Datestamp),
+}
+
+struct Includes<'r> {
+    resolver: &'r dyn Resolver,
+    /// The files currently being compiled, innermost last
+    files: Vec<PathBuf>,
+    /// The files already compiled, which any later include of them skips
+    compiled: HashSet<PathBuf>,
 }
 
-fn parse_root(ctx: &mut Context, mut root: List) -> Vec<Event> {
+fn parse_root(ctx: &mut Context, includes: &mut Includes, mut root: List) -> Result<Vec<Event>> {
     let mut current: Vec<account::ID> = Default::default();
-    root.next()
+    let mut events = Vec::new();
+    for node in root
+        .next()
         .expect("Root must have atleast 1 child")
         .into_inner()
-        .flat_map(|node| parse_declaration(ctx, &mut current, node))
-        .collect()
+    {
+        events.extend(parse_declaration(ctx, includes, &mut current, node)?);
+    }
+    Ok(events)
 }
 
 fn parse_acc_node(accounts: &mut account::Interner, node: Node) -> account::ID {
@@ -31,24 +93,416 @@ fn parse_acc_node(accounts: &mut account::Interner, node: Node) -> account::ID {
 
 fn parse_declaration<'a: 'b, 'b>(
     ctx: &mut Context<'a>,
+    includes: &mut Includes,
     current: &mut Vec<account::ID>,
     declaration: Node<'b>,
-) -> Option<Event> {
+) -> Result<Vec<Event>> {
     let node = declaration.into_child();
     match node.as_rule() {
         Rule::decl_accounts => {
             current.clear();
             for acc in node.into_inner() {
-                current.push(parse_acc_node(ctx.accounts, acc));
+                current.push(parse_account_declaration(ctx, acc)?);
+            }
+            Ok(Vec::new())
+        }
+        Rule::decl_event => Ok(vec![parse_event(ctx, current.clone(), node)?]),
+        Rule::decl_include => parse_include(ctx, includes, node),
+        Rule::decl_fx => {
+            parse_fx(ctx, includes, node)?;
+            Ok(Vec::new())
+        }
+        Rule::decl_calendar => {
+            parse_calendar(ctx, includes, node)?;
+            Ok(Vec::new())
+        }
+        Rule::decl_let => {
+            parse_let(ctx, node)?;
+            Ok(Vec::new())
+        }
+        _ => unreachable!("Unexpected rule: {:?}", node.as_rule()),
+    }
+}
+
+fn parse_account_declaration(ctx: &mut Context, node: Node) -> Result<account::ID> {
+    let mut nodes = node.into_inner().peekable();
+    let account = parse_acc_node(
+        ctx.accounts,
+        nodes.next().expect("Account must have a name"),
+    );
+    if let Some(node) = nodes.next_if(|node| node.as_rule() == Rule::account_kind) {
+        declare_kind(ctx, account, &node)?;
+    }
+    if let Some(node) = nodes.next_if(|node| node.as_rule() == Rule::commodity) {
+        declare_commodity(ctx, account, &node)?;
+    }
+    if let Some(value) = nodes.next_if(|node| node.as_rule() != Rule::account_limit) {
+        let balance = parse_constant(ctx, &value)?;
+        if let Some(node) = nodes.next_if(|node| node.as_rule() == Rule::commodity) {
+            declare_commodity(ctx, account, &node)?;
+        }
+        let date = nodes
+            .next_if(|node| node.as_rule() != Rule::account_limit)
+            .map(|node| parse_point(ctx, &node))
+            .transpose()?;
+
+        let declaration = ctx.registry.declare(account);
+        if declaration.opening.is_some() {
+            return Err(CompileError::new(
+                ErrorKind::DuplicateOpening(ctx.accounts.resolve(account).unwrap().into()),
+                &value,
+            ));
+        }
+        declaration.opening = Some(account::Opening { balance, date });
+    }
+    for node in nodes {
+        declare_limit(ctx, account, node)?;
+    }
+    Ok(account)
+}
+
+fn declare_limit(ctx: &mut Context, account: account::ID, node: Node) -> Result<()> {
+    let span = node.clone();
+    let mut nodes = node.into_inner();
+    let bound = nodes.next().expect("Limit must have a bound");
+    let value = parse_constant(ctx, &nodes.next().expect("Limit must have a value"))?;
+    let limits = &mut ctx.registry.declare(account).limits;
+    let (limit, name) = match bound.as_rule() {
+        Rule::limit_floor => (&mut limits.floor, "floor"),
+        Rule::limit_ceiling => (&mut limits.ceiling, "ceiling"),
+        _ => unreachable!("Unexpected rule: {:?}", bound.as_rule()),
+    };
+    if limit.is_some() {
+        return Err(CompileError::new(
+            ErrorKind::DuplicateLimit {
+                account: ctx.accounts.resolve(account).unwrap().into(),
+                limit: name,
+            },
+            &span,
+        ));
+    }
+    *limit = Some(value);
+    Ok(())
+}
+
+fn declare_commodity(ctx: &mut Context, account: account::ID, node: &Node) -> Result<()> {
+    let commodity = parse_commodity(ctx.accounts, node);
+    let declaration = ctx.registry.declare(account);
+    match declaration.commodity {
+        Some(existing) if existing != commodity => Err(CompileError::new(
+            ErrorKind::CommodityMismatch {
+                account: ctx.accounts.resolve(account).unwrap().into(),
+                expected: ctx.accounts.resolve(existing).unwrap().into(),
+            },
+            node,
+        )),
+        _ => {
+            declaration.commodity = Some(commodity);
+            Ok(())
+        }
+    }
+}
+
+fn declare_kind(ctx: &mut Context, account: account::ID, node: &Node) -> Result<()> {
+    let kind = match node.as_str() {
+        "asset" => account::Kind::Asset,
+        "liability" => account::Kind::Liability,
+        "income" => account::Kind::Income,
+        "expense" => account::Kind::Expense,
+        "equity" => account::Kind::Equity,
+        kind => unreachable!("Unexpected account kind: {kind}"),
+    };
+    let declaration = ctx.registry.declare(account);
+    match declaration.kind {
+        Some(existing) if existing != kind => Err(CompileError::new(
+            ErrorKind::KindMismatch {
+                account: ctx.accounts.resolve(account).unwrap().into(),
+                expected: existing.to_string(),
+            },
+            node,
+        )),
+        _ => {
+            declaration.kind = Some(kind);
+            Ok(())
+        }
+    }
+}
+
+fn parse_commodity(accounts: &mut account::Interner, node: &Node) -> account::Commodity {
+    accounts.get_or_intern(node.as_str())
+}
+
+/// An amount that must be known at compile time.
+fn parse_constant(ctx: &Context, node: &Node) -> Result<Decimal> {
+    parse_value(ctx, node)?.constant().ok_or_else(|| {
+        CompileError::new(
+            ErrorKind::VariableType {
+                name: node.as_str().into(),
+                expected: "constant amount",
+            },
+            node,
+        )
+    })
+}
+
+/// Read a file named in the source, relative to the file naming it.
+fn read_data_file(includes: &Includes, node: &Node) -> Result<(String, String)> {
+    let file = node.clone().into_child().as_str();
+    let path = includes
+        .resolver
+        .locate(file, includes.files.last().map(PathBuf::as_path));
+    let source = includes.resolver.read(&path).map_err(|e| {
+        CompileError::new(
+            ErrorKind::DataFile {
+                path: path.display().to_string(),
+                message: e.to_string(),
+            },
+            node,
+        )
+    })?;
+    Ok((path.display().to_string(), source))
+}
+
+fn parse_calendar(ctx: &mut Context, includes: &Includes, node: Node) -> Result<()> {
+    use pest::Parser;
+    let mut nodes = node.into_inner();
+    let name = nodes.next().expect("Calendar must have a name");
+    if ctx.calendars.contains_key(name.as_str()) {
+        return Err(CompileError::new(
+            ErrorKind::Redefinition(name.as_str().into()),
+            &name,
+        ));
+    }
+
+    let mut calendar = Calendar::default();
+    match nodes.peek() {
+        Some(file) if file.as_rule() == Rule::string => {
+            let (path, source) = read_data_file(includes, &file)?;
+            for (number, line) in source.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+                    continue;
+                }
+                parser::Mercury::parse(Rule::holiday_line, line)
+                    .map_err(CompileError::from)
+                    .and_then(|mut nodes| {
+                        parse_holiday(&mut calendar, nodes.next().unwrap().into_child())
+                    })
+                    .map_err(|e| {
+                        CompileError::new(
+                            ErrorKind::DataFile {
+                                path: path.clone(),
+                                message: format!("line {}: {}", number + 1, e.kind),
+                            },
+                            &file,
+                        )
+                    })?;
+            }
+        }
+        _ => {
+            for node in nodes {
+                parse_holiday(&mut calendar, node)?;
+            }
+        }
+    }
+    ctx.calendars.insert(name.as_str().into(), calendar);
+    Ok(())
+}
+
+fn parse_holiday(calendar: &mut Calendar, node: Node) -> Result<()> {
+    let node = node.into_child();
+    let rule = match node.as_rule() {
+        Rule::date => {
+            calendar.extend([parse_date(&node)?]);
+            return Ok(());
+        }
+        Rule::holiday_fixed => {
+            let mut nodes = node.into_inner();
+            let (month, day) = parse_month_day(&nodes.next().expect("Holiday must have a day"))?;
+            Holiday::Fixed {
+                month,
+                day,
+                observed: nodes.next().is_some(),
+            }
+        }
+        Rule::holiday_nth => {
+            let mut nodes = node.into_inner();
+            let nth = parse_ordinal(&nodes.next().unwrap());
+            let weekday = nodes.next().unwrap().as_str().parse().unwrap();
+            let month = nodes
+                .next()
+                .unwrap()
+                .as_str()
+                .parse::<chrono::Month>()
+                .unwrap();
+            Holiday::Nth {
+                month: month.number_from_month(),
+                weekday,
+                nth,
+            }
+        }
+        Rule::holiday_easter => {
+            let mut nodes = node.into_inner();
+            let mut days = 0;
+            if let Some(sign) = nodes.next() {
+                let period = nodes.next().expect("Offset must have a period");
+                days = match parse_period(&period)? {
+                    Period::Days(n) => n as i32,
+                    Period::Weeks(n) => n as i32 * 7,
+                    Period::Months(_) | Period::Years(_) => {
+                        return Err(CompileError::new(
+                            ErrorKind::Syntax("expected a number of days or weeks".into()),
+                            &period,
+                        ))
+                    }
+                };
+                if sign.as_rule() == Rule::time_offset_sub {
+                    days = -days;
+                }
             }
-            None
+            Holiday::Easter(days)
         }
-        Rule::decl_event => Some(parse_event(ctx, current.clone(), node)),
         _ => unreachable!("Unexpected rule: {:?}", node.as_rule()),
+    };
+    calendar.push(rule);
+    Ok(())
+}
+
+/// A day of the year as a month and day, which may be 29 February.
+fn parse_month_day(node: &Node) -> Result<(u32, u32)> {
+    let invalid = || CompileError::new(ErrorKind::InvalidDate(node.as_str().into()), node);
+    let (month, day) = node.as_str().split_once('-').ok_or_else(invalid)?;
+    let (month, day) = (
+        month.parse().map_err(|_| invalid())?,
+        day.parse().map_err(|_| invalid())?,
+    );
+    // Any day that exists in a leap year
+    Datestamp::from_ymd_opt(2000, month, day).ok_or_else(invalid)?;
+    Ok((month, day))
+}
+
+/// The position an ordinal names, counting from the end if negative.
+fn parse_ordinal(node: &Node) -> i8 {
+    let mut words = node.as_str().split_whitespace();
+    let nth = match words.next().unwrap() {
+        "first" => 1,
+        "second" => 2,
+        "third" => 3,
+        "fourth" => 4,
+        "fifth" => 5,
+        "last" => return -1,
+        ordinal => unreachable!("Unexpected ordinal: {}", ordinal),
+    };
+    match words.next() {
+        Some(_) => -nth,
+        None => nth,
     }
 }
 
-fn parse_event(ctx: &mut Context, current: Vec<account::ID>, event: Node) -> Event {
+/// The named calendars, merged.
+fn parse_calendar_names<'n>(
+    ctx: &Context,
+    calendar: &mut Calendar,
+    nodes: impl Iterator<Item = Node<'n>>,
+) -> Result<()> {
+    for node in nodes {
+        let named = ctx.calendars.get(node.as_str()).ok_or_else(|| {
+            CompileError::new(ErrorKind::UnknownCalendar(node.as_str().into()), &node)
+        })?;
+        calendar.include(node.as_str(), named);
+    }
+    Ok(())
+}
+
+fn parse_fx(ctx: &mut Context, includes: &Includes, node: Node) -> Result<()> {
+    let mut nodes = node.into_inner();
+    let first = nodes.next().expect("Rate must have a pair or file");
+    if first.as_rule() == Rule::string {
+        let (path, source) = read_data_file(includes, &first)?;
+        return ctx
+            .registry
+            .rates_mut()
+            .load(&source, ctx.accounts)
+            .map_err(|message| CompileError::new(ErrorKind::DataFile { path, message }, &first));
+    }
+
+    let mut pair = first.into_inner();
+    let base = parse_commodity(ctx.accounts, &pair.next().expect("Pair must have a base"));
+    let quote = parse_commodity(ctx.accounts, &pair.next().expect("Pair must have a quote"));
+    let rate = parse_constant(ctx, &nodes.next().expect("Pair must have a rate"))?;
+    let date = nodes
+        .next()
+        .map(|node| parse_point(ctx, &node))
+        .transpose()?;
+    ctx.registry.rates_mut().insert(base, quote, rate, date);
+    Ok(())
+}
+
+fn parse_let(ctx: &mut Context, node: Node) -> Result<()> {
+    let mut nodes = node.into_inner();
+    let name = nodes.next().expect("Let must have a name");
+    // These would always be read as the keyword, never the variable
+    if ["start", "today", "end", "work", "self", "super"].contains(&name.as_str()) {
+        return Err(CompileError::new(
+            ErrorKind::ReservedName(name.as_str().into()),
+            &name,
+        ));
+    }
+    if ctx.variables.contains_key(name.as_str()) {
+        return Err(CompileError::new(
+            ErrorKind::Redefinition(name.as_str().into()),
+            &name,
+        ));
+    }
+
+    let node = nodes.next().expect("Let must have a value");
+    let value = match node.as_rule() {
+        Rule::time_offset | Rule::date | Rule::time_func_keyword => {
+            Value::Date(parse_point(ctx, &node)?)
+        }
+        Rule::expr => Value::Amount(parse_expression(ctx, node)?),
+        _ => unreachable!("Unexpected rule: {:?}", node.as_rule()),
+    };
+    ctx.variables.insert(name.as_str().into(), value);
+    Ok(())
+}
+
+fn parse_variable<'c>(ctx: &'c Context, node: &Node) -> Result<&'c Value> {
+    ctx.variables
+        .get(node.as_str())
+        .ok_or_else(|| CompileError::new(ErrorKind::UndefinedVariable(node.as_str().into()), node))
+}
+
+fn parse_include(ctx: &mut Context, includes: &mut Includes, node: Node) -> Result<Vec<Event>> {
+    let include = node.clone().into_child().into_child().as_str().to_owned();
+    let path = includes
+        .resolver
+        .locate(&include, includes.files.last().map(PathBuf::as_path));
+    if includes.files.contains(&path) {
+        return Err(CompileError::new(
+            ErrorKind::IncludeCycle {
+                path: path.display().to_string(),
+            },
+            &node,
+        ));
+    }
+    // A file reached through more than one include is only compiled once
+    if includes.compiled.contains(&path) {
+        return Ok(Vec::new());
+    }
+    let source = includes.resolver.read(&path).map_err(|e| {
+        CompileError::new(
+            ErrorKind::Include {
+                path: path.display().to_string(),
+                message: e.to_string(),
+            },
+            &node,
+        )
+    })?;
+    compile_unit(ctx, includes, path, &source)
+}
+
+fn parse_event(ctx: &mut Context, current: Vec<account::ID>, event: Node) -> Result<Event> {
     let mut nodes = event.into_inner();
     let schedule = parse_schedule(
         ctx,
@@ -56,105 +510,295 @@ fn parse_event(ctx: &mut Context, current: Vec<account::ID>, event: Node) -> Eve
             .next()
             .expect("Event must have a schedule")
             .into_child(),
-    );
+    )?;
     let statements = nodes.next().expect("Event must have a statements node");
 
-    Event {
+    Ok(Event {
         schedule,
         accounts: current,
-        operations: parse_statements(ctx, statements),
-    }
+        operations: parse_statements(ctx, statements)?,
+    })
 }
 
-fn parse_schedule(ctx: &mut Context, node: Node) -> Schedule {
-    match node.as_rule() {
+fn parse_schedule(ctx: &mut Context, node: Node) -> Result<Schedule> {
+    Ok(match node.as_rule() {
         Rule::time => {
             let node = node.into_child();
             match node.as_rule() {
-                Rule::cron => Schedule::Cron(
-                    crate::schedule::Cron::from_str(format!("0 0 12 {}", node.as_str()).as_str())
-                        .expect("Pest should have validated the cron"),
-                ),
-                Rule::date => {
-                    let seperator = node
-                        .as_str()
-                        .chars()
-                        .nth(4)
-                        .expect("Date must have a separator");
-                    Schedule::Date(
-                        Datestamp::parse_from_str(
-                            node.as_str(),
-                            &format!("%Y{}%m{}%d", seperator, seperator),
-                        )
-                        .unwrap(),
-                    )
-                }
+                Rule::cron => parse_cron(&node)?,
+                Rule::date => Schedule::Date(parse_date(&node)?),
                 _ => unreachable!("Unexpected rule: {:?}", node.as_rule()),
             }
         }
-        Rule::time_function => {
-            let node = node.into_child();
-            match node.as_rule() {
-                Rule::time_func_not => {
-                    Schedule::TimeFunctionNot(Box::new(parse_schedule(ctx, node.into_child())))
-                }
-                Rule::time_func_and => {
-                    let mut nodes = node.into_inner();
-                    Schedule::TimeFunctionAnd(
-                        Box::new(parse_schedule(ctx, nodes.next().unwrap())),
-                        Box::new(parse_schedule(ctx, nodes.next().unwrap())),
-                    )
-                }
-                Rule::time_func_or => {
-                    let mut nodes = node.into_inner();
-                    let mut schedule = parse_schedule(ctx, nodes.next().unwrap());
-                    for node in nodes {
-                        schedule = Schedule::TimeFunctionOr(
-                            Box::new(schedule),
-                            Box::new(parse_schedule(ctx, node)),
-                        );
-                    }
-                    schedule
-                }
-                Rule::time_func_lt => {
-                    let mut nodes = node.into_inner();
-                    Schedule::TimeFunctionBefore(
-                        Box::new(parse_schedule(ctx, nodes.next().unwrap())),
-                        Box::new(parse_schedule(ctx, nodes.next().unwrap())),
-                    )
+        Rule::time_function => parse_schedule(ctx, node.into_child())?,
+        Rule::time_func_not => {
+            Schedule::TimeFunctionNot(Box::new(parse_schedule(ctx, node.into_child())?))
+        }
+        Rule::time_func_and => {
+            let mut nodes = node.into_inner();
+            Schedule::TimeFunctionAnd(
+                Box::new(parse_schedule(ctx, nodes.next().unwrap())?),
+                Box::new(parse_schedule(ctx, nodes.next().unwrap())?),
+            )
+        }
+        Rule::time_func_or => {
+            let mut nodes = node.into_inner();
+            let mut schedule = parse_schedule(ctx, nodes.next().unwrap())?;
+            for node in nodes {
+                schedule = Schedule::TimeFunctionOr(
+                    Box::new(schedule),
+                    Box::new(parse_schedule(ctx, node)?),
+                );
+            }
+            schedule
+        }
+        Rule::time_func_lt => {
+            let mut nodes = node.into_inner();
+            Schedule::TimeFunctionBefore(
+                Box::new(parse_schedule(ctx, nodes.next().unwrap())?),
+                Box::new(parse_schedule(ctx, nodes.next().unwrap())?),
+            )
+        }
+        Rule::time_func_gt => {
+            let mut nodes = node.into_inner();
+            Schedule::TimeFunctionAfter(
+                Box::new(parse_schedule(ctx, nodes.next().unwrap())?),
+                Box::new(parse_schedule(ctx, nodes.next().unwrap())?),
+            )
+        }
+        Rule::time_func_by => {
+            let mut nodes = node.into_inner();
+            Schedule::TimeFunctionBy(
+                Box::new(parse_schedule(ctx, nodes.next().unwrap())?),
+                Box::new(parse_schedule(ctx, nodes.next().unwrap())?),
+            )
+        }
+        Rule::time_func_times | Rule::time_func_take | Rule::time_func_skip => {
+            let rule = node.as_rule();
+            let mut nodes = node.into_inner();
+            let (count, schedule) = match rule {
+                Rule::time_func_times => {
+                    let schedule = nodes.next().unwrap();
+                    (nodes.next().unwrap(), schedule)
                 }
-                Rule::time_func_gt => {
-                    let mut nodes = node.into_inner();
-                    Schedule::TimeFunctionAfter(
-                        Box::new(parse_schedule(ctx, nodes.next().unwrap())),
-                        Box::new(parse_schedule(ctx, nodes.next().unwrap())),
-                    )
+                _ => (nodes.next().unwrap(), nodes.next().unwrap()),
+            };
+            let count = count.as_str().parse().map_err(|_| {
+                CompileError::new(ErrorKind::InvalidNumber(count.as_str().into()), &count)
+            })?;
+            let schedule = parse_schedule(ctx, schedule)?;
+            let anchor = match nodes.next() {
+                Some(node) => parse_point(ctx, &node)?,
+                None => ctx.date_start,
+            };
+            match rule {
+                Rule::time_func_skip => schedule.skip(count, anchor),
+                _ => schedule.take(count, anchor),
+            }
+        }
+        Rule::time_func_offset => {
+            let mut nodes = node.into_inner();
+            let mut schedule = parse_schedule(ctx, nodes.next().unwrap())?;
+            while let Some(op) = nodes.next() {
+                let period = parse_period(&nodes.next().expect("Offset must have a period"))?;
+                let shift = match op.as_rule() {
+                    Rule::time_offset_add => Shift::Later(period),
+                    Rule::time_offset_sub => Shift::Earlier(period),
+                    _ => unreachable!("Unexpected rule: {:?}", op.as_rule()),
+                };
+                schedule = Schedule::Offset(Box::new(schedule), shift);
+            }
+            schedule
+        }
+        Rule::time_func_every => {
+            let mut nodes = node.into_inner();
+            let period = nodes.next().expect("Every must have a period");
+            let step = parse_period(&period)?;
+            if step.is_zero() {
+                return Err(CompileError::new(
+                    ErrorKind::InvalidNumber(period.as_str().into()),
+                    &period,
+                ));
+            }
+            let anchor = match nodes.next() {
+                Some(node) => parse_point(ctx, &node)?,
+                None => ctx.date_start,
+            };
+            Schedule::Every { anchor, step }
+        }
+        Rule::time_func_roll => {
+            let mut nodes = node.into_inner();
+            let convention = nodes.next().expect("Roll must have a convention");
+            let convention = match convention.as_str().split_whitespace().collect::<Vec<_>>()[..] {
+                ["following"] => Roll::Following,
+                ["preceding"] => Roll::Preceding,
+                ["modified", "following"] => Roll::ModifiedFollowing,
+                ["modified", "preceding"] => Roll::ModifiedPreceding,
+                _ => unreachable!("Unexpected convention: {}", convention.as_str()),
+            };
+            let schedule = parse_schedule(ctx, nodes.next().expect("Roll must have a schedule"))?;
+            let mut calendar = ctx.holidays.clone();
+            parse_calendar_names(ctx, &mut calendar, nodes)?;
+            Schedule::Roll(Box::new(schedule), convention, calendar)
+        }
+        Rule::time_func_nth => {
+            let mut nodes = node.into_inner();
+            let nth = parse_ordinal(&nodes.next().expect("Nth must have an ordinal"));
+            let node = nodes.next().expect("Nth must have a day");
+            match node.as_rule() {
+                Rule::weekday => Schedule::Weekday {
+                    weekday: node.as_str().parse().unwrap(),
+                    nth,
+                },
+                Rule::workday => {
+                    let mut calendar = ctx.holidays.clone();
+                    parse_calendar_names(ctx, &mut calendar, node.into_inner().skip(1))?;
+                    Schedule::WorkDay(nth, calendar)
                 }
-                Rule::time_func_by => {
-                    let mut nodes = node.into_inner();
-                    Schedule::TimeFunctionBy(
-                        Box::new(parse_schedule(ctx, nodes.next().unwrap())),
-                        Box::new(parse_schedule(ctx, nodes.next().unwrap())),
-                    )
+                _ => Schedule::MonthDay(nth),
+            }
+        }
+        Rule::time_func_season => {
+            let mut nodes = node.into_inner();
+            Schedule::Season {
+                start: parse_month_day(&nodes.next().expect("Season must have a start"))?,
+                end: parse_month_day(&nodes.next().expect("Season must have an end"))?,
+            }
+        }
+        Rule::time_func_work => {
+            let mut calendar = ctx.holidays.clone();
+            for node in node.into_inner() {
+                match node.as_rule() {
+                    Rule::date => calendar.extend([parse_date(&node)?]),
+                    _ => parse_calendar_names(ctx, &mut calendar, [node].into_iter())?,
                 }
-                _ => unreachable!("Unexpected rule: {:?}", node.as_rule()),
             }
+            Schedule::Work(calendar)
         }
+        Rule::time_func_holidays => {
+            let mut calendar = Calendar::default();
+            parse_calendar_names(ctx, &mut calendar, node.into_inner())?;
+            Schedule::Holidays(calendar)
+        }
+        Rule::time_func_keyword if node.as_str() == "work" => Schedule::Work(ctx.holidays.clone()),
+        Rule::time_func_keyword | Rule::variable | Rule::time_offset => {
+            Schedule::Date(parse_point(ctx, &node)?)
+        }
+        _ => unreachable!("Unexpected rule: {:?}", node.as_rule()),
+    })
+}
+
+/// A single date: a literal, a keyword, a variable or an offset from any of them.
+fn parse_point(ctx: &Context, node: &Node) -> Result<Datestamp> {
+    match node.as_rule() {
+        Rule::date => parse_date(node),
         Rule::time_func_keyword => match node.as_str() {
-            "today" => {
-                let now = chrono::Local::now();
-                Schedule::Date(now.date_naive())
-            }
-            "start" => Schedule::Date(ctx.date_start),
-            "end" => Schedule::Date(ctx.date_end - chrono::Duration::days(1)),
-            "work" => todo!("work"),
-            _ => unreachable!("Unexpected rule: {:?}", node.as_rule()),
+            "today" => Ok(chrono::Local::now().date_naive()),
+            "start" => Ok(ctx.date_start),
+            "end" => Ok(ctx.date_end - chrono::Duration::days(1)),
+            "work" => Err(CompileError::new(
+                ErrorKind::ExpectedDate(node.as_str().into()),
+                node,
+            )),
+            keyword => Err(CompileError::new(
+                ErrorKind::UnknownKeyword(keyword.into()),
+                node,
+            )),
+        },
+        Rule::variable => match parse_variable(ctx, node)? {
+            Value::Date(date) => Ok(*date),
+            Value::Amount(_) => Err(CompileError::new(
+                ErrorKind::VariableType {
+                    name: node.as_str().into(),
+                    expected: "date",
+                },
+                node,
+            )),
         },
+        Rule::time_offset => {
+            let mut nodes = node.clone().into_inner();
+            let mut date = parse_point(ctx, &nodes.next().expect("Offset must have a date"))?;
+            while let Some(op) = nodes.next() {
+                let period = parse_period(&nodes.next().expect("Offset must have a period"))?;
+                date = match op.as_rule() {
+                    Rule::time_offset_add => period.after(date),
+                    Rule::time_offset_sub => period.before(date),
+                    _ => unreachable!("Unexpected rule: {:?}", op.as_rule()),
+                }
+                .ok_or_else(|| {
+                    CompileError::new(ErrorKind::InvalidDate(node.as_str().into()), node)
+                })?;
+            }
+            Ok(date)
+        }
         _ => unreachable!("Unexpected rule: {:?}", node.as_rule()),
     }
 }
 
-fn parse_statements(ctx: &mut Context, node: Node) -> Statements {
+fn parse_period(node: &Node) -> Result<Period> {
+    let text = node.as_str();
+    let (count, unit) = text.split_at(text.len() - 1);
+    let count = count
+        .parse()
+        .map_err(|_| CompileError::new(ErrorKind::InvalidNumber(text.into()), node))?;
+    Ok(match unit {
+        "d" => Period::Days(count),
+        "w" => Period::Weeks(count),
+        "m" => Period::Months(count),
+        "y" => Period::Years(count),
+        _ => unreachable!("Unexpected period: {}", text),
+    })
+}
+
+fn parse_cron(node: &Node) -> Result<Schedule> {
+    if let Some(quartz) = node
+        .clone()
+        .into_inner()
+        .flatten()
+        .find(|node| node.as_rule() == Rule::cron_quartz)
+    {
+        let feature = if quartz.as_str().contains('#') {
+            "`#` (nth weekday of the month)"
+        } else if quartz.as_str().ends_with('W') {
+            "`W` (nearest weekday)"
+        } else {
+            "`L` (last day)"
+        };
+        return Err(CompileError::new(
+            ErrorKind::InvalidCron(format!("{feature} is not supported")),
+            &quartz,
+        ));
+    }
+
+    Schedule::cron(node.as_str()).map_err(|e| {
+        // The engine echoes the expression we built with a caret under the field it stopped at,
+        // then explains the problem, except for a year it could not read
+        let message = e.to_string();
+        let mut lines = message.lines();
+        let expression = lines.next().unwrap_or_default();
+        let caret = lines.next().and_then(|line| line.find('^'));
+        let (read, rest) = expression.split_at(caret.unwrap_or_default().min(expression.len()));
+        let field = rest.split_whitespace().next().unwrap_or_default();
+        // Seconds, minutes and hours come before the fields in the source
+        let reason = match lines.map(str::trim).find(|line| !line.is_empty()) {
+            Some(line) => line.to_owned(),
+            None if read.split_whitespace().count() == 6 => {
+                format!("'{field}' is not a year from 1970 to 2100.")
+            }
+            None => format!("'{field}' is not valid."),
+        };
+        CompileError::new(ErrorKind::InvalidCron(reason), node)
+    })
+}
+
+fn parse_date(node: &Node) -> Result<Datestamp> {
+    let invalid = || CompileError::new(ErrorKind::InvalidDate(node.as_str().into()), node);
+    let seperator = node.as_str().chars().nth(4).ok_or_else(invalid)?;
+    Datestamp::parse_from_str(node.as_str(), &format!("%Y{}%m{}%d", seperator, seperator))
+        .map_err(|_| invalid())
+}
+
+fn parse_statements(ctx: &mut Context, node: Node) -> Result<Statements> {
     match node.as_rule() {
         rule @ Rule::statements_list | rule @ Rule::statements_set => {
             let mut nodes = node.into_inner();
@@ -164,181 +808,305 @@ fn parse_statements(ctx: &mut Context, node: Node) -> Statements {
                 .expect("Statements list must have atleast 1 statement");
             let second = nodes.next();
 
-            let (acc, mut stmts) = if let Some(stmts) = second {
-                (
-                    parse_acc_node(ctx.accounts, first),
-                    stmts
-                        .into_inner()
-                        .map(|stmt| parse_statements(ctx, stmt))
-                        .collect::<Vec<_>>(),
-                )
-            } else {
-                (
-                    ctx.accounts
-                        .get_or_intern_static(if rule == Rule::statements_set {
-                            "new"
-                        } else {
-                            "self"
-                        }),
-                    first
-                        .into_inner()
-                        .map(|stmt| parse_statements(ctx, stmt))
-                        .collect(),
-                )
+            let (acc, stmts) = match second {
+                Some(stmts) => (Some(parse_acc_node(ctx.accounts, first)), stmts),
+                None => (None, first),
             };
+            let stmts = stmts
+                .into_inner()
+                .map(|stmt| parse_statements(ctx, stmt))
+                .collect::<Result<Vec<_>>>()?;
 
-            let sym_self = ctx.accounts.get_or_intern_static("self");
-            stmts.push(Statements::Single(Statement {
-                from: sym_self,
-                func: Box::new(move |ctx| ctx[sym_self]),
-                to: ctx.accounts.get_or_intern_static("super"),
-                label: None,
-            }));
-
-            if rule == Rule::statements_set {
-                Statements::Set(acc, stmts)
+            Ok(if rule == Rule::statements_set {
+                Statements::set(ctx.accounts, acc, stmts)
             } else {
-                Statements::List(acc, stmts)
-            }
+                Statements::list(ctx.accounts, acc, stmts)
+            })
         }
-        Rule::statements_single => Statements::Single(parse_statement(ctx, node.into_inner())),
+        Rule::statements_single => Ok(Statements::Single(parse_statement(ctx, node.into_inner())?)),
         Rule::statements => parse_statements(ctx, node.into_child()),
         _ => unreachable!("Unexpected rule: {:?}", node.as_rule()),
     }
 }
 
-fn parse_statement(ctx: &mut Context, mut nodes: List) -> Statement {
-    Statement {
-        from: parse_acc_node(
-            ctx.accounts,
-            nodes.next().expect("Statement must have a from account"),
-        ),
-        func: parse_operation(
-            ctx.accounts,
-            nodes
-                .next()
-                .expect("Statement must have an operation")
-                .into_inner(),
-        ),
+fn parse_statement(ctx: &mut Context, mut nodes: List) -> Result<Statement> {
+    let from = parse_acc_node(
+        ctx.accounts,
+        nodes.next().expect("Statement must have a from account"),
+    );
+    let (func, commodity) = parse_operation(
+        ctx,
+        nodes
+            .next()
+            .expect("Statement must have an operation")
+            .into_inner(),
+    )?;
+    Ok(Statement {
+        from,
+        func,
         to: parse_acc_node(
             ctx.accounts,
             nodes.next().expect("Statement must have a to account"),
         ),
+        commodity,
         label: nodes.next().map(|node| node.as_str().trim().into()),
-    }
+    })
 }
 
-fn parse_operation(accounts: &mut account::Interner, mut nodes: List) -> Operation {
+fn parse_operation(
+    ctx: &mut Context,
+    nodes: List,
+) -> Result<(Operation, Option<account::Commodity>)> {
+    let mut nodes = nodes.peekable();
     let first = nodes.next().expect("Operation must have atleast 1 node");
-    match first.as_rule() {
-        Rule::amount => {
-            let amount = parse_amount(first);
+    let commodity = nodes
+        .next_if(|node| node.as_rule() == Rule::commodity)
+        .map(|node| parse_commodity(ctx.accounts, &node));
+    let func = match first.as_rule() {
+        Rule::amount | Rule::variable => {
+            let amount = parse_value(ctx, &first)?;
             if let Some(modifier) = nodes.next() {
-                parse_operation_mod(accounts, amount, modifier)
+                parse_operation_mod(ctx.accounts, amount, modifier)
             } else {
-                Box::new(move |_| amount)
+                amount
             }
+            .into_operation()
         }
-        Rule::func => parse_operation_func(accounts, first.into_child()),
+        Rule::func => parse_operation_func(ctx, first.into_child())?,
         _ => unreachable!("Unexpected rule: {:?}", first.as_rule()),
+    };
+    Ok((func, commodity))
+}
+
+fn parse_value(ctx: &Context, node: &Node) -> Result<Expression> {
+    match node.as_rule() {
+        Rule::amount => Ok(Expression::Number(parse_amount(node)?)),
+        Rule::variable => match parse_variable(ctx, node)? {
+            Value::Amount(expr) => Ok(expr.clone()),
+            Value::Date(_) => Err(CompileError::new(
+                ErrorKind::VariableType {
+                    name: node.as_str().into(),
+                    expected: "amount",
+                },
+                node,
+            )),
+        },
+        _ => unreachable!("Unexpected rule: {:?}", node.as_rule()),
     }
 }
 
-fn parse_amount(node: Node) -> f64 {
+fn parse_amount(node: &Node) -> Result<Decimal> {
     node.as_str()
         .replace("_", "")
         .parse()
-        .expect("Amount must be a valid number")
+        .map_err(|_| CompileError::new(ErrorKind::InvalidNumber(node.as_str().into()), node))
 }
 
-fn parse_operation_mod(accounts: &mut account::Interner, amount: f64, node: Node) -> Operation {
+fn parse_operation_mod(
+    accounts: &mut account::Interner,
+    amount: Expression,
+    node: Node,
+) -> Expression {
     let node = node.into_inner().next();
     match node {
         Some(node) if node.as_rule() == Rule::rate => {
-            let rate = parse_operation_rate(node);
-            Box::new(move |_| amount * rate)
+            let (numerator, denominator) = parse_operation_rate(node);
+            amount.ratio(numerator, denominator)
         }
         Some(node) if node.as_rule() == Rule::account_id => {
-            let sym = parse_acc_node(accounts, node);
-            Box::new(move |ctx| amount / 100.0 * ctx[sym])
-        }
-        None => {
-            let sym_self = accounts.get_or_intern_static("self");
-            Box::new(move |ctx| amount / 100.0 * ctx[sym_self])
+            amount.percent_of(parse_acc_node(accounts, node))
         }
+        None => amount.percent_of(accounts.get_or_intern_static("self")),
         Some(node) => unreachable!("Unexpected rule: {:?}", node.as_rule()),
     }
 }
 
-fn parse_operation_rate(node: Node) -> f64 {
+/// The ratio of one period to another, as a numerator and denominator.
+fn parse_operation_rate(node: Node) -> (i32, i32) {
     let mut nodes = node.into_inner();
     let lhs = nodes.next().expect("Rate must have a lhs");
     let rhs = nodes.next().expect("Rate must have a rhs");
 
     match lhs.as_str().chars().next().unwrap() {
         'y' => match rhs.as_str().chars().next().unwrap() {
-            'y' => 1.0,
-            'q' => 1.0 / 4.0,
-            'm' => 1.0 / 12.0,
-            'w' => 1.0 / 52.0,
-            'd' => 1.0 / 365.0,
+            'y' => (1, 1),
+            'q' => (1, 4),
+            'm' => (1, 12),
+            'w' => (1, 52),
+            'd' => (1, 365),
             _ => unreachable!("Unexpected rate: {}", rhs.as_str()),
         },
         'q' => match rhs.as_str().chars().next().unwrap() {
-            'y' => 4.0,
-            'q' => 1.0,
-            'm' => 1.0 / 3.0,
-            'w' => 1.0 / 13.0,
-            'd' => 4.0 / 365.0,
+            'y' => (4, 1),
+            'q' => (1, 1),
+            'm' => (1, 3),
+            'w' => (1, 13),
+            'd' => (4, 365),
             _ => unreachable!("Unexpected rate: {}", rhs.as_str()),
         },
         'm' => match rhs.as_str().chars().next().unwrap() {
-            'y' => 12.0,
-            'q' => 3.0,
-            'm' => 1.0,
-            'w' => 1.0 / 4.0,
-            'd' => 1.0 / 30.0,
+            'y' => (12, 1),
+            'q' => (3, 1),
+            'm' => (1, 1),
+            'w' => (1, 4),
+            'd' => (1, 30),
             _ => unreachable!("Unexpected rate: {}", rhs.as_str()),
         },
         'w' => match rhs.as_str().chars().next().unwrap() {
-            'y' => 52.0,
-            'q' => 13.0,
-            'm' => 4.0,
-            'w' => 1.0,
-            'd' => 1.0 / 7.0,
+            'y' => (52, 1),
+            'q' => (13, 1),
+            'm' => (4, 1),
+            'w' => (1, 1),
+            'd' => (1, 7),
             _ => unreachable!("Unexpected rate: {}", rhs.as_str()),
         },
         'd' => match rhs.as_str().chars().next().unwrap() {
-            'y' => 365.0,
-            'q' => 91.0,
-            'm' => 30.0,
-            'w' => 7.0,
-            'd' => 1.0,
+            'y' => (365, 1),
+            'q' => (91, 1),
+            'm' => (30, 1),
+            'w' => (7, 1),
+            'd' => (1, 1),
             _ => unreachable!("Unexpected rate: {}", rhs.as_str()),
         },
         _ => unreachable!("Unexpected rate: {}", lhs.as_str()),
     }
 }
 
-fn parse_operation_func(_accounts: &mut account::Interner, node: Node) -> Operation {
-    todo!("parse_operation_func: '{}'", node.as_str())
+fn parse_operation_func(ctx: &mut Context, node: Node) -> Result<Operation> {
+    Ok(parse_expression(ctx, node)?.into_operation())
 }
 
-pub fn compile(mut ctx: Context, source: impl AsRef<str>) -> Vec<Event> {
-    use pest::Parser;
-    match parser::Mercury::parse(Rule::root, source.as_ref().trim()) {
-        Ok(parsed) => parse_root(&mut ctx, parsed),
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            panic!("Compile Panic! {e}");
-        }
+fn parse_expression(ctx: &mut Context, node: Node) -> Result<Expression> {
+    use pest::pratt_parser::{Assoc, Op, PrattParser};
+    static PRATT: LazyLock<PrattParser<Rule>> = LazyLock::new(|| {
+        PrattParser::new()
+            .op(Op::infix(Rule::expr_add, Assoc::Left) | Op::infix(Rule::expr_sub, Assoc::Left))
+            .op(Op::infix(Rule::expr_mul, Assoc::Left) | Op::infix(Rule::expr_div, Assoc::Left))
+            .op(Op::prefix(Rule::expr_neg))
+            .op(Op::postfix(Rule::expr_percent))
+    });
+
+    PRATT
+        .map_primary(|node| match node.as_rule() {
+            Rule::amount | Rule::variable => parse_value(ctx, &node),
+            Rule::expr => parse_expression(ctx, node),
+            Rule::expr_account => Ok(Expression::Account(parse_acc_node(
+                ctx.accounts,
+                node.into_child(),
+            ))),
+            Rule::expr_call => parse_expression_call(ctx, node),
+            _ => unreachable!("Unexpected rule: {:?}", node.as_rule()),
+        })
+        .map_prefix(|op, rhs| match op.as_rule() {
+            Rule::expr_neg => Ok(Expression::Neg(Box::new(rhs?))),
+            _ => unreachable!("Unexpected rule: {:?}", op.as_rule()),
+        })
+        .map_postfix(|lhs, op| match op.as_rule() {
+            Rule::expr_percent => Ok(lhs?.percent()),
+            _ => unreachable!("Unexpected rule: {:?}", op.as_rule()),
+        })
+        .map_infix(|lhs, op, rhs| {
+            let (lhs, rhs) = (Box::new(lhs?), Box::new(rhs?));
+            Ok(match op.as_rule() {
+                Rule::expr_add => Expression::Add(lhs, rhs),
+                Rule::expr_sub => Expression::Sub(lhs, rhs),
+                Rule::expr_mul => Expression::Mul(lhs, rhs),
+                Rule::expr_div => Expression::Div(lhs, rhs),
+                _ => unreachable!("Unexpected rule: {:?}", op.as_rule()),
+            })
+        })
+        .parse(node.into_inner())
+}
+
+fn parse_expression_call(ctx: &mut Context, node: Node) -> Result<Expression> {
+    let span = node.clone();
+    let mut nodes = node.into_inner();
+    let name = nodes.next().expect("Call must have a function name");
+    let func = Function::from_name(name.as_str()).ok_or_else(|| {
+        CompileError::new(ErrorKind::UnsupportedFunction(name.as_str().into()), &name)
+    })?;
+    let args = nodes
+        .map(|arg| parse_expression(ctx, arg))
+        .collect::<Result<Vec<_>>>()?;
+    if !func.arity().contains(&args.len()) {
+        return Err(CompileError::new(
+            ErrorKind::ArgumentCount {
+                function: func.name().into(),
+                found: args.len(),
+            },
+            &span,
+        ));
     }
+    Ok(Expression::Call(func, args))
+}
+
+fn compile_unit(
+    ctx: &mut Context,
+    includes: &mut Includes,
+    path: PathBuf,
+    source: &str,
+) -> Result<Vec<Event>> {
+    use pest::Parser;
+    includes.files.push(path);
+    let events = parser::Mercury::parse(Rule::root, source)
+        .map_err(CompileError::from)
+        .and_then(|parsed| parse_root(ctx, includes, parsed));
+    let path = includes
+        .files
+        .pop()
+        .expect("Unit must be on the include stack");
+    let events = events.map_err(|e| e.or_path(&path))?;
+    includes.compiled.insert(path);
+    Ok(events)
+}
+
+/// Compile a source with no file of its own.
+/// Any includes are resolved on the filesystem, relative to the working directory.
+pub fn compile(mut ctx: Context, source: impl AsRef<str>) -> Result<Vec<Event>> {
+    use pest::Parser;
+    let mut includes = Includes {
+        resolver: &Filesystem,
+        files: Vec::new(),
+        compiled: HashSet::new(),
+    };
+    let parsed = parser::Mercury::parse(Rule::root, source.as_ref())?;
+    parse_root(&mut ctx, &mut includes, parsed)
+}
+
+/// Compile the contents of the file at `path`, resolving includes relative to it.
+pub fn compile_file(
+    mut ctx: Context,
+    resolver: &dyn Resolver,
+    path: impl AsRef<Path>,
+    source: impl AsRef<str>,
+) -> Result<Vec<Event>> {
+    let path = resolver.locate(&path.as_ref().to_string_lossy(), None);
+    let mut includes = Includes {
+        resolver,
+        files: Vec::new(),
+        compiled: HashSet::new(),
+    };
+    compile_unit(&mut ctx, &mut includes, path, source.as_ref())
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn compile_str(source: &str) -> Result<Vec<Event>> {
+        let mut accounts = account::Interner::default();
+        let mut registry = account::Registry::default();
+        compile(
+            Context::new(
+                &mut accounts,
+                &mut registry,
+                date("2021-01-01"),
+                date("2021-12-31"),
+            ),
+            source,
+        )
+    }
+
     #[test]
     fn blah() {
         const TEST: &str = r#"
@@ -346,14 +1114,21 @@ mod test {
 "#;
         use pest::Parser;
         let mut accounts = account::Interner::default();
-        let mut ctx = Context {
-            accounts: &mut accounts,
-            date_start: Datestamp::parse_from_str("2021-01-01", "%Y-%m-%d").unwrap(),
-            date_end: Datestamp::parse_from_str("2021-12-31", "%Y-%m-%d").unwrap(),
-        };
+        let mut registry = account::Registry::default();
+        let mut ctx = Context::new(
+            &mut accounts,
+            &mut registry,
+            date("2021-01-01"),
+            date("2021-12-31"),
+        );
         match parser::Mercury::parse(Rule::root, TEST.trim()) {
             Ok(parsed) => {
-                let events = parse_root(&mut ctx, parsed);
+                let mut includes = Includes {
+                    resolver: &Filesystem,
+                    files: Vec::new(),
+                    compiled: HashSet::new(),
+                };
+                let events = parse_root(&mut ctx, &mut includes, parsed);
                 println!("{:#?}", events);
             }
             Err(e) => {
@@ -361,4 +1136,1583 @@ mod test {
             }
         }
     }
+
+    fn date(string: &str) -> Datestamp {
+        Datestamp::parse_from_str(string, "%Y-%m-%d").unwrap()
+    }
+
+    fn money(string: &str) -> account::Money {
+        string.parse().unwrap()
+    }
+
+    #[test]
+    fn work_holidays() {
+        let mut accounts = account::Interner::default();
+        let mut registry = account::Registry::default();
+        let events = compile(
+            Context {
+                holidays: Calendar::new([date("2024-05-31")]),
+                ..Context::new(&mut accounts, &mut registry, date("2024-01-01"), date("2024-12-31"))
+            },
+            "<a>\n(by(work; (1 * *))) [void > 10 > self]\n(by(work(2024-06-28); (1 * *))) [void > 10 > self]",
+        )
+        .unwrap();
+
+        let mut upcoming = events[0].schedule.upcoming(date("2024-05-01"));
+        assert_eq!(upcoming.next(), Some(date("2024-05-30")));
+        assert_eq!(upcoming.next(), Some(date("2024-06-28")));
+
+        let mut upcoming = events[1].schedule.upcoming(date("2024-05-01"));
+        assert_eq!(upcoming.next(), Some(date("2024-05-30")));
+        assert_eq!(upcoming.next(), Some(date("2024-06-27")));
+    }
+
+    #[test]
+    fn comments() {
+        let mut accounts = account::Interner::default();
+        let mut registry = account::Registry::default();
+        let events = compile(
+            Context::new(
+                &mut accounts,
+                &mut registry,
+                date("2024-01-01"),
+                date("2024-12-31"),
+            ),
+            r#"
+// Household accounts
+<a, /* the other one */ b>
+/* Paid on the third,
+   or the fifth */
+((3 * *) // third
+ | /* fifth */ (5 * *)) [
+    void > 4_000 > self: Salary // before tax
+    , self > 20% / 12 > b, // pension
+    /* ignored */
+]
+(3 * *) [void > 10 > self: "not // a comment"] // trailing
+"#,
+        )
+        .unwrap();
+        assert_eq!(events.len(), 2);
+
+        let Statements::List(_, stmts) = &events[0].operations else {
+            panic!("Expected a list");
+        };
+        let Statements::Single(salary) = &stmts[0] else {
+            panic!("Expected a statement");
+        };
+        assert_eq!(salary.label.as_deref(), Some("Salary"));
+
+        let Statements::List(_, stmts) = &events[1].operations else {
+            panic!("Expected a list");
+        };
+        let Statements::Single(stmt) = &stmts[0] else {
+            panic!("Expected a statement");
+        };
+        assert_eq!(stmt.label.as_deref(), Some(r#""not // a comment""#));
+    }
+
+    fn compile_files(files: &[(&str, &str)]) -> (Result<Vec<Event>>, account::Interner) {
+        let files = files
+            .iter()
+            .map(|(path, source)| (PathBuf::from(path), source.to_string()))
+            .collect::<std::collections::HashMap<_, _>>();
+        let mut accounts = account::Interner::default();
+        let mut registry = account::Registry::default();
+        let events = compile_file(
+            Context::new(
+                &mut accounts,
+                &mut registry,
+                date("2024-01-01"),
+                date("2024-12-31"),
+            ),
+            &files,
+            "budget/main.mercury",
+            &files[Path::new("budget/main.mercury")],
+        );
+        (events, accounts)
+    }
+
+    #[test]
+    fn include() {
+        let (events, accounts) = compile_files(&[
+            (
+                "budget/main.mercury",
+                "include \"income/salary.mercury\"\n<current> (1 * *) [current > 900 > rent]",
+            ),
+            (
+                "budget/income/salary.mercury",
+                "include \"../shared.mercury\"\n<current> (28 * *) [void > 2_000 > self]",
+            ),
+            (
+                "budget/shared.mercury",
+                "<current> (3 * *) [current > 5 > void]",
+            ),
+        ]);
+        let events = events.unwrap();
+        assert_eq!(events.len(), 3);
+        let current = accounts.get("current").unwrap();
+        assert!(events.iter().all(|event| event.accounts == [current]));
+    }
+
+    #[test]
+    fn include_diamond() {
+        let (events, accounts) = compile_files(&[
+            (
+                "budget/main.mercury",
+                "include \"a.mercury\"\ninclude \"b.mercury\"",
+            ),
+            ("budget/a.mercury", "include \"shared.mercury\""),
+            ("budget/b.mercury", "include \"./shared.mercury\""),
+            (
+                "budget/shared.mercury",
+                "let rent = 900\ncalendar office { 12-25 }\n<current> (1 * *) [self > rent > void]",
+            ),
+        ]);
+        let events = events.unwrap();
+        assert_eq!(events.len(), 1);
+        let current = accounts.get("current").unwrap();
+        assert_eq!(events[0].accounts, [current]);
+    }
+
+    #[test]
+    fn error_include_cycle() {
+        let (events, _) = compile_files(&[
+            ("budget/main.mercury", "include \"a.mercury\""),
+            ("budget/a.mercury", "include \"./b.mercury\""),
+            ("budget/b.mercury", "include \"../budget/a.mercury\""),
+        ]);
+        let error = events.unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::IncludeCycle {
+                path: "budget/a.mercury".into()
+            }
+        );
+        assert_eq!(error.path(), Some(Path::new("budget/b.mercury")));
+    }
+
+    #[test]
+    fn error_include_path() {
+        let (events, _) = compile_files(&[
+            (
+                "budget/main.mercury",
+                "include \"a.mercury\"\ninclude \"b.mercury\"",
+            ),
+            ("budget/a.mercury", "<a> (3 * *) [void > 5 > self]"),
+            ("budget/b.mercury", "<a>\n(2024-02-31) [void > 5 > self]"),
+        ]);
+        let error = events.unwrap_err();
+        assert_eq!(error.kind, ErrorKind::InvalidDate("2024-02-31".into()));
+        assert_eq!(error.path(), Some(Path::new("budget/b.mercury")));
+        assert!(error.to_string().contains("--> budget/b.mercury:2:2"));
+
+        let (events, _) = compile_files(&[("budget/main.mercury", "include \"missing.mercury\"")]);
+        let error = events.unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::Include { .. }));
+        assert_eq!(error.path(), Some(Path::new("budget/main.mercury")));
+    }
+
+    #[test]
+    fn variables() {
+        let mut accounts = account::Interner::default();
+        let mut registry = account::Registry::default();
+        let mut ctx = Context::new(
+            &mut accounts,
+            &mut registry,
+            date("2024-01-01"),
+            date("2024-12-31"),
+        );
+        let events = {
+            use pest::Parser;
+            let parsed = parser::Mercury::parse(
+                Rule::root,
+                r#"
+let salary = 45_000
+let rent = 1_350
+let tax = (salary - 12_570) * 20%
+let retirement = 2024-06-01
+let starting = 2024-02-01
+<current>
+(((28 * *) > starting) < retirement) [void > salary y/m > self, self > rent > void, self > tax / 12 > void]
+(retirement) [void > max(0, salary - <current>) > self]
+"#,
+            )
+            .unwrap();
+            let mut includes = Includes {
+                resolver: &Filesystem,
+                files: Vec::new(),
+                compiled: HashSet::new(),
+            };
+            parse_root(&mut ctx, &mut includes, parsed).unwrap()
+        };
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            ctx.variables["rent"],
+            Value::Amount(Expression::Number(Decimal::from(1_350)))
+        );
+        assert_eq!(ctx.variables["retirement"], Value::Date(date("2024-06-01")));
+
+        let upcoming = events[0].schedule.upcoming(date("2024-01-01"));
+        assert_eq!(
+            upcoming.collect::<Vec<_>>(),
+            ["2024-02-28", "2024-03-28", "2024-04-28", "2024-05-28"].map(date)
+        );
+    }
+
+    #[test]
+    fn error_variables() {
+        let error = compile_str("let a = 5\nlet a = 6").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Redefinition("a".into()));
+        assert_eq!(error.span.start, Location { line: 2, column: 5 });
+
+        for name in ["start", "today", "end", "work", "self", "super"] {
+            let error = compile_str(&format!("let a = 5\nlet {name} = 6")).unwrap_err();
+            assert_eq!(error.kind, ErrorKind::ReservedName(name.into()));
+            assert_eq!(error.span.start, Location { line: 2, column: 5 });
+            assert_eq!(error.span.end.column, 5 + name.len());
+        }
+        compile_str("let starting = 2024-01-01\nlet selfish = 5").unwrap();
+
+        let error = compile_str("<a> (3 * *) [void > b * 2 > self]").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::UndefinedVariable("b".into()));
+
+        let error = compile_str("let b = 5\n<a> ((3 * *) < b) [void > 2 > self]").unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::VariableType {
+                name: "b".into(),
+                expected: "date"
+            }
+        );
+
+        let error = compile_str("let b = 2024-01-01\n<a> (3 * *) [void > b > self]").unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::VariableType {
+                name: "b".into(),
+                expected: "amount"
+            }
+        );
+    }
+
+    #[test]
+    fn cron() {
+        let upcoming = |source: &str| {
+            let events = compile_str(&format!("<a> ({source}) [void > 1 > self]")).unwrap();
+            events[0]
+                .schedule
+                .upcoming(date("2024-01-01"))
+                .take(4)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            upcoming("1,15,28 * *"),
+            ["2024-01-01", "2024-01-15", "2024-01-28", "2024-02-01"].map(date)
+        );
+        assert_eq!(
+            upcoming("1-5/2 * *"),
+            ["2024-01-01", "2024-01-03", "2024-01-05", "2024-02-01"].map(date)
+        );
+        assert_eq!(
+            upcoming("*/10 FEB,MAR ?"),
+            ["2024-02-01", "2024-02-11", "2024-02-21", "2024-03-01"].map(date)
+        );
+        assert_eq!(
+            upcoming("* JAN MON-FRI"),
+            ["2024-01-01", "2024-01-02", "2024-01-03", "2024-01-04"].map(date)
+        );
+        assert_eq!(
+            upcoming("3 jun * 2025-2026"),
+            ["2025-06-03", "2026-06-03"].map(date)
+        );
+    }
+
+    #[test]
+    fn error_cron() {
+        let error = compile_str("<a> (L * *) [void > 1 > self]").unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::InvalidCron("`L` (last day) is not supported".into())
+        );
+        assert_eq!(error.span.start, Location { line: 1, column: 6 });
+
+        let error = compile_str("<a> (* * 1,FRI#3) [void > 1 > self]").unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::InvalidCron("`#` (nth weekday of the month) is not supported".into())
+        );
+        assert_eq!(
+            error.span.start,
+            Location {
+                line: 1,
+                column: 12
+            }
+        );
+
+        let error = compile_str("<a> (15W * *) [void > 1 > self]").unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::InvalidCron("`W` (nearest weekday) is not supported".into())
+        );
+
+        let error = compile_str("<a> (1 FOO *) [void > 1 > self]").unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::InvalidCron("'FOO' is not a valid month name.".into())
+        );
+
+        for year in ["1969", "9999"] {
+            let error = compile_str(&format!("<a> (3 * * {year}) [void > 1 > self]")).unwrap_err();
+            assert_eq!(
+                error.kind,
+                ErrorKind::InvalidCron(format!("'{year}' is not a year from 1970 to 2100."))
+            );
+        }
+
+        let error = compile_str("<a> (32 * *) [void > 1 > self]").unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::InvalidCron("Days of Month must be less than 31. ('32' specified.)".into())
+        );
+    }
+
+    #[test]
+    fn offsets() {
+        let mut accounts = account::Interner::default();
+        let mut registry = account::Registry::default();
+        let events = compile(
+            Context::new(
+                &mut accounts,
+                &mut registry,
+                date("2024-01-31"),
+                date("2024-12-31"),
+            ),
+            r#"
+let review = start + 2y - 1d
+<a>
+(start + 3m) [void > 1 > self]
+(2025-01-31 - 1w) [void > 1 > self]
+(2025-01-31 + 1m + 1m) [void > 1 > self]
+(review) [void > 1 > self]
+((28 * *) < start + 3m) [void > 1 > self]
+((28 * *) > end - 2m) [void > 1 > self]
+(today + 14d) [void > 1 > self]
+"#,
+        )
+        .unwrap();
+        let first = |event: &Event| event.schedule.upcoming(date("2024-01-01")).next();
+
+        assert_eq!(first(&events[0]), Some(date("2024-04-30")));
+        assert_eq!(first(&events[1]), Some(date("2025-01-24")));
+        assert_eq!(first(&events[2]), Some(date("2025-03-28")));
+        assert_eq!(first(&events[3]), Some(date("2026-01-30")));
+        assert_eq!(
+            events[4]
+                .schedule
+                .upcoming(date("2024-01-01"))
+                .collect::<Vec<_>>(),
+            ["2024-01-28", "2024-02-28", "2024-03-28", "2024-04-28"].map(date)
+        );
+        assert_eq!(first(&events[5]), Some(date("2024-11-28")));
+
+        let today = chrono::Local::now().date_naive();
+        assert_eq!(
+            events[6].schedule.upcoming(today).next(),
+            Some(today + chrono::Duration::days(14))
+        );
+    }
+
+    #[test]
+    fn error_offsets() {
+        let error = compile_str("<a> (work + 1d) [void > 1 > self]").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::ExpectedDate("work".into()));
+
+        let error = compile_str("let a = 5\n<a> (a + 1d) [void > 1 > self]").unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::VariableType {
+                name: "a".into(),
+                expected: "date"
+            }
+        );
+    }
+
+    #[test]
+    fn error_syntax() {
+        let error = compile_str("<a>\n(3 * *) [void > 4_000 >]").unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::Syntax(_)));
+        assert_eq!(error.span.start.line, 2);
+    }
+
+    #[test]
+    fn error_invalid_date() {
+        let error = compile_str("\n<a>\n(2021-02-30) [void > 10 > self]").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::InvalidDate("2021-02-30".into()));
+        assert_eq!(error.span.start, Location { line: 3, column: 2 });
+        assert_eq!(
+            error.span.end,
+            Location {
+                line: 3,
+                column: 12
+            }
+        );
+        assert_eq!(error.source_line(), "(2021-02-30) [void > 10 > self]");
+        assert_eq!(
+            error.to_string(),
+            "error: invalid date `2021-02-30`\n \
+             --> 3:2\n  \
+             |\n\
+             3 | (2021-02-30) [void > 10 > self]\n  \
+             |  ^^^^^^^^^^"
+        );
+    }
+
+    #[test]
+    fn error_invalid_cron() {
+        let error = compile_str("<a> (31 2-1 *) [void > 10 > self]").unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::InvalidCron(_)));
+        assert_eq!(error.span.start, Location { line: 1, column: 6 });
+    }
+
+    #[test]
+    fn error_unsupported_function() {
+        let error = compile_str("<a> (3 * *) [void > magic(1) > self]").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::UnsupportedFunction("magic".into()));
+    }
+
+    #[test]
+    fn error_argument_count() {
+        let error = compile_str("<a> (3 * *) [void > clamp(1, 2) > self]").unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::ArgumentCount {
+                function: "clamp".into(),
+                found: 2
+            }
+        );
+    }
+
+    fn expression(source: &str) -> (Expression, account::Interner) {
+        use pest::Parser;
+        let mut accounts = account::Interner::default();
+        let mut registry = account::Registry::default();
+        let node = parser::Mercury::parse(Rule::expr, source)
+            .unwrap()
+            .next()
+            .unwrap();
+        let mut ctx = Context::new(
+            &mut accounts,
+            &mut registry,
+            date("2021-01-01"),
+            date("2021-12-31"),
+        );
+        (parse_expression(&mut ctx, node).unwrap(), accounts)
+    }
+
+    fn eval(source: &str, balances: &[(&str, i32)]) -> String {
+        let (expr, mut accounts) = expression(source);
+        let mut stack = account::Stack::new(account::Symbols::new(&mut accounts));
+        for (name, balance) in balances {
+            stack[accounts.get_or_intern(name)] = Decimal::from(*balance);
+        }
+        expr.eval(&account::CtxMut::new(&mut stack, &mut accounts))
+            .to_string()
+    }
+
+    #[test]
+    fn expression_precedence() {
+        assert_eq!(eval("1 + 2 * 3", &[]), "7");
+        assert_eq!(eval("(1 + 2) * 3", &[]), "9");
+        assert_eq!(eval("10 - 4 - 3", &[]), "3");
+        assert_eq!(eval("-2 * 3", &[]), "-6");
+        assert_eq!(eval("50% / 2", &[]), "0.25");
+        assert_eq!(eval("0.1 + 0.2", &[]), "0.3");
+    }
+
+    #[test]
+    fn expression_accounts() {
+        let balances = [("salary", 42_570), ("current", 800), ("savings", 9_000)];
+        assert_eq!(eval("(<salary> - 12_570) * 20% / 12", &balances), "500");
+        assert_eq!(eval("min(<current>, 500)", &balances), "500");
+        assert_eq!(eval("max(0, <savings> - 10_000)", &balances), "0");
+        assert_eq!(eval("round(<current> / 3, 2)", &balances), "266.67");
+        assert_eq!(eval("round(2.5) + round(-2.5)", &balances), "0");
+        assert_eq!(eval("floor(-<current> / 3, 1)", &balances), "-266.7");
+        assert_eq!(eval("ceil(<current> / 3)", &balances), "267");
+        assert_eq!(eval("clamp(<current>, 0, 100)", &balances), "100");
+    }
+
+    #[test]
+    fn expression_out_of_range() {
+        let balances = [("current", 800), ("empty", 0)];
+        assert_eq!(eval("<current> / <empty>", &balances), "0");
+        assert_eq!(eval("round(<current> / 0, 2)", &balances), "0");
+
+        let (events, accounts, registry) = compile_openings(
+            "<a> (3 * *) [void > 10 > self, self > 5 / <b> > c]
+<a> (4 * *) [void > 170_000_000_000_000_000_000_000_000 * 2 > self]",
+        )
+        .unwrap();
+        let c = accounts.get("c").unwrap();
+        let mut timeline = crate::Timeline::new(&events, accounts, registry);
+        timeline
+            .process(date("2024-03-01"), date("2024-06-01"))
+            .for_each(drop);
+        assert_eq!(timeline.balances()[&c], Decimal::ZERO);
+    }
+
+    #[test]
+    fn expression_statement() {
+        let events = compile_str(
+            "<a> (3 * *) [void > 100 y/m > self, void > 5 % a > self, void > -(<a> + 1) % > self, \
+             void > (<salary> - 12_570) * 20% / 12 > self, self > min(self, 5) > b]",
+        )
+        .unwrap();
+        assert_eq!(events.len(), 1);
+    }
+
+    fn compile_openings(
+        source: &str,
+    ) -> Result<(Vec<Event>, account::Interner, account::Registry)> {
+        let mut accounts = account::Interner::default();
+        let mut registry = account::Registry::default();
+        let events = compile(
+            Context {
+                variables: HashMap::from([(
+                    "buffer".into(),
+                    Value::Amount(Expression::Number(Decimal::from(500))),
+                )]),
+                ..Context::new(
+                    &mut accounts,
+                    &mut registry,
+                    date("2024-03-01"),
+                    date("2024-06-01"),
+                )
+            },
+            source,
+        )?;
+        Ok((events, accounts, registry))
+    }
+
+    #[test]
+    fn openings() {
+        let (events, accounts, registry) = compile_openings(
+            "<current = 2_400, savings = 15_000 on 2024-01-01, emergency = buffer>
+<savings> (1 * *) [void > 100 > self]",
+        )
+        .unwrap();
+        let opening = |name| registry.get(accounts.get(name).unwrap()).unwrap().opening;
+        assert_eq!(
+            opening("current"),
+            Some(account::Opening {
+                balance: Decimal::from(2_400),
+                date: None
+            })
+        );
+        assert_eq!(
+            opening("savings"),
+            Some(account::Opening {
+                balance: Decimal::from(15_000),
+                date: Some(date("2024-01-01"))
+            })
+        );
+        assert_eq!(opening("emergency").unwrap().balance, Decimal::from(500));
+
+        let savings = accounts.get("savings").unwrap();
+        let current = accounts.get("current").unwrap();
+        let mut timeline = crate::Timeline::new(&events, accounts, registry);
+        timeline
+            .process(date("2024-03-01"), date("2024-06-01"))
+            .for_each(drop);
+
+        // Opening balances are the first data point, with January and February rolled forward,
+        // and each event from the first of March on is a point of its own
+        assert_eq!(
+            timeline.dates(),
+            ["2024-03-01", "2024-03-01", "2024-04-01", "2024-05-01"].map(date)
+        );
+        assert_eq!(
+            timeline.history()[&current],
+            (date("2024-03-01"), vec![Decimal::from(2_400); 4])
+        );
+        assert_eq!(
+            timeline.history()[&savings],
+            (
+                date("2024-03-01"),
+                ["15200", "15300", "15400", "15500"].map(money).to_vec()
+            )
+        );
+    }
+
+    #[test]
+    fn opening_day_events() {
+        let (events, accounts, registry) = compile_openings(
+            "<savings = 1_000 on 2024-02-15, current = 50 on 2024-03-01>
+<savings> (15 * *) [void > 10 > self]
+<current> (1 * *) [self > 5 > savings]",
+        )
+        .unwrap();
+        let savings = accounts.get("savings").unwrap();
+        let current = accounts.get("current").unwrap();
+        let mut timeline = crate::Timeline::new(&events, accounts, registry);
+        let dates = timeline
+            .process(date("2024-03-01"), date("2024-06-01"))
+            .map(|moment| moment.date)
+            .collect::<Vec<_>>();
+
+        // Events on an opening date run after it opens, and those on the first day are processed
+        assert_eq!(
+            dates,
+            [
+                "2024-03-01",
+                "2024-03-15",
+                "2024-04-01",
+                "2024-04-15",
+                "2024-05-01",
+                "2024-05-15"
+            ]
+            .map(date)
+        );
+        assert_eq!(
+            timeline.history()[&savings].1,
+            ["1010", "1015", "1025", "1030", "1040", "1045", "1055"].map(money)
+        );
+        assert_eq!(
+            timeline.history()[&current].1,
+            ["50", "45", "45", "40", "40", "35", "35"].map(money)
+        );
+    }
+
+    #[test]
+    fn mixed_openings() {
+        let opened = |source: &str| {
+            let (events, accounts, registry) = compile_openings(source).unwrap();
+            let mut timeline = crate::Timeline::new(&events, accounts, registry);
+            timeline
+                .process(date("2024-03-01"), date("2024-06-01"))
+                .for_each(drop);
+            let first = |name| {
+                let id = timeline.lookup(name)?;
+                Some(timeline.history()[&id].1[0].to_string())
+            };
+            (first("current"), first("savings"), first("spend"))
+        };
+        let events = "<current> (1 * *) [void > 100 > self, self > 5 > spend]
+<savings> (1 * *) [void > 10 > self]";
+
+        // Only the account with a dated opening is rolled forward to the first of March, and the
+        // others start from their opening or nothing
+        assert_eq!(
+            opened(&format!(
+                "<current = 1000, savings = 0 on 2024-01-01>\n{events}"
+            )),
+            (Some("1000".into()), Some("20".into()), Some("5".into()))
+        );
+        assert_eq!(
+            opened(&format!("<current = 1000>\n{events}")),
+            (Some("1000".into()), Some("10".into()), Some("5".into()))
+        );
+    }
+
+    #[test]
+    fn interest_to_the_penny() {
+        let (events, accounts, registry) = compile_openings(
+            "<current = 1_000>
+<current> (every(1d)) [void > <current> * 5% / 365 > self]",
+        )
+        .unwrap();
+        let current = accounts.get("current").unwrap();
+        let mut timeline = crate::Timeline::new(&events, accounts, registry);
+        timeline
+            .process(date("2024-03-01"), date("2024-06-01"))
+            .for_each(drop);
+        // Every day's interest is rounded to the penny as it is posted
+        assert_eq!(timeline.balances()[&current].to_string(), "1012.88");
+    }
+
+    #[test]
+    fn idle_events() {
+        let (events, accounts, registry) = compile_openings(
+            "<current = 100>
+((3 * *) & (4 * *)) [void > 1 > self]
+(1 * *) [void > 1 > self]
+(2025-01-01) [void > 1 > self]",
+        )
+        .unwrap();
+        let current = accounts.get("current").unwrap();
+        let mut timeline = crate::Timeline::new(&events, accounts, registry);
+        timeline
+            .process(date("2024-03-01"), date("2024-06-01"))
+            .for_each(drop);
+        assert_eq!(timeline.idle(), &[0, 2]);
+        assert_eq!(timeline.balances()[&current], Decimal::from(103));
+    }
+
+    #[test]
+    fn error_openings() {
+        let error = compile_openings("<current = 1>\n<savings, current = 2>").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::DuplicateOpening("current".into()));
+        assert_eq!(
+            error.span.start,
+            Location {
+                line: 2,
+                column: 21
+            }
+        );
+
+        let error = compile_openings("<current = <savings>>").unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::Syntax(_)));
+
+        let error = compile_openings("let rate = <savings> * 2\n<current = rate>").unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::VariableType {
+                name: "rate".into(),
+                expected: "constant amount"
+            }
+        );
+    }
+
+    #[test]
+    fn label_colon() {
+        let mut accounts = account::Interner::default();
+        let mut registry = account::Registry::default();
+        let events = compile(
+            Context::new(
+                &mut accounts,
+                &mut registry,
+                date("2024-01-01"),
+                date("2024-12-31"),
+            ),
+            "<current> (1 * *) [self > 10 > savings:Bonus, self > 10 > savings: Bonus, self > 10 > savings :Bonus]",
+        )
+        .unwrap();
+        let Statements::List(_, stmts) = &events[0].operations else {
+            panic!("Expected a list");
+        };
+        let statement = |index: usize| {
+            let Statements::Single(stmt) = &stmts[index] else {
+                panic!("Expected a statement");
+            };
+            (stmt.to, stmt.label.as_deref())
+        };
+        let (savings, bonus) = (
+            accounts.get("savings").unwrap(),
+            accounts.get("savings:Bonus").unwrap(),
+        );
+        assert_eq!(statement(0), (bonus, None));
+        assert_eq!(statement(1), (savings, Some("Bonus")));
+        assert_eq!(statement(2), (savings, Some("Bonus")));
+    }
+
+    #[test]
+    fn hierarchy() {
+        let (events, accounts, registry) = compile_openings(
+            "<assets:bank:current = 100, assets:bank:savings = 50, assets:cash = 10>
+<assets:bank:current> (1 * *) [self > 5 > expenses:food: Groceries, void > 1 > assets]",
+        )
+        .unwrap();
+        let Statements::List(_, stmts) = &events[0].operations else {
+            panic!("Expected a list");
+        };
+        let Statements::Single(groceries) = &stmts[0] else {
+            panic!("Expected a statement");
+        };
+        assert_eq!(groceries.label.as_deref(), Some("Groceries"));
+
+        let mut timeline = crate::Timeline::new(&events, accounts, registry);
+        timeline
+            .process(date("2024-03-01"), date("2024-06-01"))
+            .for_each(drop);
+        let id = |name| timeline.lookup(name).unwrap();
+        let (assets, bank) = (id("assets"), id("assets:bank"));
+
+        // Parents include their own postings and everything below them
+        let balances = timeline.balances();
+        assert_eq!(balances[&assets], Decimal::from(148));
+        assert_eq!(balances[&bank], Decimal::from(135));
+        assert_eq!(balances[&id("expenses")], Decimal::from(15));
+        assert_eq!(
+            timeline.history()[&assets],
+            (
+                date("2024-03-01"),
+                ["160", "156", "152", "148"].map(money).to_vec()
+            )
+        );
+        assert_eq!(
+            timeline.history()[&id("expenses")],
+            (date("2024-03-01"), ["5", "10", "15"].map(money).to_vec())
+        );
+
+        use crate::Resolve;
+        let subtree = |depth| {
+            let mut names = timeline
+                .subtree(assets, depth)
+                .into_iter()
+                .map(|(acc, balance)| (timeline.resolve(acc), balance.to_string()))
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        assert_eq!(subtree(0), [("assets", "148".to_owned())]);
+        assert_eq!(
+            subtree(1),
+            [
+                ("assets", "148".to_owned()),
+                ("assets:bank", "135".to_owned()),
+                ("assets:cash", "10".to_owned())
+            ]
+        );
+        assert_eq!(subtree(2).len(), 5);
+    }
+
+    #[test]
+    fn kinds() {
+        let (events, accounts, registry) = compile_openings(
+            "<void as income, current as asset = 1_000, card as liability, expenses as expense>
+<refunds as expense>
+<current> (1 * *) [
+    void > 2_000 > self,
+    self > 1_500 > expenses:rent,
+    card > 300 > expenses:food,
+    expenses:food > 20 > self,
+    refunds > 5 > self,
+]",
+        )
+        .unwrap();
+        let mut timeline = crate::Timeline::new(&events, accounts, registry);
+        timeline
+            .process(date("2024-03-01"), date("2024-06-01"))
+            .for_each(drop);
+        let id = |name| timeline.lookup(name).unwrap();
+        assert_eq!(
+            timeline.kind(id("expenses:food")),
+            Some(account::Kind::Expense)
+        );
+        assert_eq!(timeline.kind(id("self")), None);
+
+        let totals = |total: std::result::Result<Vec<account::Money>, crate::fx::MissingRate>| {
+            total
+                .unwrap()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            totals(timeline.net_worth(None)),
+            ["1000", "1225", "1450", "1675"]
+        );
+        assert_eq!(totals(timeline.income(None)), ["0", "2000", "4000", "6000"]);
+        assert_eq!(
+            totals(timeline.expenses(None)),
+            ["0", "1775", "3550", "5325"]
+        );
+
+        // Only the refunds have flowed out of an expense overall
+        assert_eq!(
+            timeline.wrong_signs(),
+            [account::WrongSign {
+                account: id("refunds"),
+                kind: account::Kind::Expense,
+                balance: Decimal::from(-15),
+            }]
+        );
+
+        let error = compile_openings("<a as asset>\n<a as expense>").unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::KindMismatch {
+                account: "a".into(),
+                expected: "asset".into()
+            }
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let source = "<current = 100 >= -50, isa <= 50>
+<current> (1 * *) [self > 100 > spend, void > 30 > isa]";
+        let run = |policy| {
+            let (events, accounts, registry) = compile_openings(source).unwrap();
+            let id = |name| accounts.get(name).unwrap();
+            let (current, isa, spend) = (id("current"), id("isa"), id("spend"));
+            let mut timeline = crate::Timeline::new(&events, accounts, registry);
+            timeline.set_policy(policy);
+            timeline
+                .process(date("2024-03-01"), date("2024-06-01"))
+                .for_each(drop);
+            let balances = timeline.balances();
+            let violations = timeline
+                .violations()
+                .iter()
+                .map(|v| {
+                    (
+                        v.date,
+                        v.event,
+                        v.statement.to,
+                        v.account,
+                        v.limit,
+                        v.balance,
+                    )
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(timeline.aborted(), policy == account::Policy::Abort);
+            (
+                [current, isa, spend].map(|acc| balances.get(&acc).copied().unwrap_or_default()),
+                violations,
+                [current, isa, spend],
+            )
+        };
+        let floor = account::Limit::Floor(Decimal::from(-50));
+        let ceiling = account::Limit::Ceiling(Decimal::from(50));
+
+        let (balances, violations, [current, isa, spend]) = run(account::Policy::Warn);
+        assert_eq!(balances, [-200, 90, 300].map(Decimal::from));
+        let broken = |day, current_balance: i32, isa_balance: i32| {
+            [
+                (
+                    date(day),
+                    0,
+                    spend,
+                    current,
+                    floor,
+                    Decimal::from(current_balance),
+                ),
+                (date(day), 0, isa, isa, ceiling, Decimal::from(isa_balance)),
+            ]
+        };
+        assert_eq!(
+            violations,
+            [
+                broken("2024-04-01", -100, 60),
+                broken("2024-05-01", -200, 90)
+            ]
+            .concat()
+        );
+
+        // Clamped balances stay at their limits, so later months break them by less
+        let (balances, clamped, _) = run(account::Policy::Clamp);
+        assert_eq!(balances, [-50, 50, 150].map(Decimal::from));
+        assert_eq!(
+            clamped,
+            [
+                broken("2024-04-01", -100, 60),
+                broken("2024-05-01", -150, 80)
+            ]
+            .concat()
+        );
+
+        // The first violation is not applied, and nothing after it is processed
+        let (balances, aborted, _) = run(account::Policy::Abort);
+        assert_eq!(balances, [0, 30, 100].map(Decimal::from));
+        assert_eq!(aborted, violations[..1]);
+
+        let error = compile_openings("<current >= 0 <= 10 >= -5>").unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::DuplicateLimit {
+                account: "current".into(),
+                limit: "floor"
+            }
+        );
+    }
+
+    #[test]
+    fn limits_in_sets() {
+        let run = |policy| {
+            let (events, accounts, registry) = compile_openings(
+                "<current = 100 >= 0>
+<x> (2024-03-01) {current > 60 > a, current > 60 > b}",
+            )
+            .unwrap();
+            let id = |name| accounts.get(name).unwrap();
+            let ids = [id("current"), id("a"), id("b")];
+            let mut timeline = crate::Timeline::new(&events, accounts, registry);
+            timeline.set_policy(policy);
+            timeline
+                .process(date("2024-03-01"), date("2024-06-01"))
+                .for_each(drop);
+            let balances = timeline.balances();
+            let violations = timeline
+                .violations()
+                .iter()
+                .map(|v| (v.statement.to, v.account, v.balance))
+                .collect::<Vec<_>>();
+            (
+                ids.map(|acc| balances.get(&acc).copied().unwrap_or_default()),
+                violations,
+                ids,
+            )
+        };
+
+        // Side by side statements are held to the limits together, so the second breaks them
+        let (balances, violations, [current, _, b]) = run(account::Policy::Warn);
+        assert_eq!(balances, [-20, 60, 60].map(Decimal::from));
+        assert_eq!(violations, [(b, current, Decimal::from(-20))]);
+
+        let (balances, clamped, _) = run(account::Policy::Clamp);
+        assert_eq!(balances, [0, 60, 40].map(Decimal::from));
+        assert_eq!(clamped, violations);
+
+        let (balances, aborted, _) = run(account::Policy::Abort);
+        assert_eq!(balances, [40, 60, 0].map(Decimal::from));
+        assert_eq!(aborted, violations);
+    }
+
+    #[test]
+    fn commodities() {
+        let (events, accounts, registry) = compile_openings(
+            "fx EUR/GBP = 0.8
+fx EUR/GBP = 0.9 on 2024-04-15
+fx USD/GBP = 0.75
+<current in GBP = 1_000, eur = 500 EUR, usd in USD>
+<current> (1 * *) [self > 100 EUR > eur, self > 10 > usd]",
+        )
+        .unwrap();
+        let id = |name| accounts.get(name).unwrap();
+        let (gbp, eur, usd) = (id("GBP"), id("EUR"), id("USD"));
+        assert_eq!(registry.commodity(id("eur")), Some(eur));
+        let rates = registry.rates();
+        assert_eq!(rates.rate(eur, gbp, date("2024-04-14")), Some(money("0.8")));
+        assert_eq!(
+            rates.rate(gbp, eur, date("2024-04-15")),
+            Some(Decimal::ONE / money("0.9"))
+        );
+        assert_eq!(
+            rates.rate(usd, eur, date("2024-01-01")),
+            Some(money("0.9375"))
+        );
+
+        let accs = (id("current"), id("eur"), id("usd"));
+        let mut timeline = crate::Timeline::new(&events, accounts, registry);
+        timeline
+            .process(date("2024-03-01"), date("2024-06-01"))
+            .for_each(drop);
+        assert!(timeline.missing_rates().is_empty());
+
+        // Native balances, with EUR bought at the rate on the day
+        let history = timeline.history();
+        assert_eq!(history[&accs.0].1, ["1000", "910", "820", "720"].map(money));
+        assert_eq!(history[&accs.1].1, ["500", "600", "700", "800"].map(money));
+        // Each posting is rounded to the penny before it is added
+        assert_eq!(history[&accs.2].1, ["13.33", "26.66", "39.99"].map(money));
+
+        let history = timeline.history_in(gbp).unwrap();
+        assert_eq!(history[&accs.0].1, ["1000", "910", "820", "720"].map(money));
+        assert_eq!(history[&accs.1].1, ["400", "480", "560", "720"].map(money));
+        assert_eq!(history[&accs.2].1, ["10", "20", "29.99"].map(money));
+    }
+
+    #[test]
+    fn commodities_missing_rate() {
+        let (events, accounts, registry) = compile_openings(
+            "<current in GBP = 1_000, yen in JPY>
+<current> (1 * *) [self > 10 > yen]",
+        )
+        .unwrap();
+        let (gbp, jpy) = (accounts.get("GBP").unwrap(), accounts.get("JPY").unwrap());
+        let mut timeline = crate::Timeline::new(&events, accounts, registry);
+        timeline
+            .process(date("2024-03-01"), date("2024-06-01"))
+            .for_each(drop);
+        let missing = crate::fx::MissingRate { from: gbp, to: jpy };
+        assert_eq!(
+            timeline.missing_rates().iter().collect::<Vec<_>>(),
+            [&missing]
+        );
+        assert_eq!(
+            timeline.history_in(gbp).unwrap_err(),
+            crate::fx::MissingRate { from: jpy, to: gbp }
+        );
+    }
+
+    #[test]
+    fn commodities_rate_file() {
+        let (events, accounts) = compile_files(&[
+            ("budget/main.mercury", "fx \"rates/fx.csv\"\n<eur = 10 EUR>"),
+            (
+                "budget/rates/fx.csv",
+                "# base,quote,rate,date\nEUR,GBP,0.8\nEUR,GBP,0.9,2024-04-15\n",
+            ),
+        ]);
+        events.unwrap();
+        assert!(accounts.get("GBP").is_some());
+
+        let (events, _) = compile_files(&[("budget/main.mercury", "<eur in EUR = 10 GBP>")]);
+        let error = events.unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::CommodityMismatch {
+                account: "eur".into(),
+                expected: "EUR".into()
+            }
+        );
+
+        let (events, _) = compile_files(&[
+            ("budget/main.mercury", "fx \"fx.csv\""),
+            ("budget/fx.csv", "EUR,GBP\n"),
+        ]);
+        assert_eq!(
+            events.unwrap_err().kind,
+            ErrorKind::DataFile {
+                path: "budget/fx.csv".into(),
+                message: "line 1: expected `BASE,QUOTE,RATE[,DATE]`".into()
+            }
+        );
+    }
+
+    #[test]
+    fn every() {
+        let events = compile_str(
+            "let payday = 2021-01-08
+<a> (every(4w; payday)) [void > 2_000 > self]
+(every(1m; 2021-01-31)) [void > 10 > self]
+(every(2w)) [void > 1 > self]",
+        )
+        .unwrap();
+        let upcoming = |index: usize| {
+            events[index]
+                .schedule
+                .upcoming(date("2021-01-01"))
+                .take(3)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            upcoming(0),
+            [date("2021-01-08"), date("2021-02-05"), date("2021-03-05")]
+        );
+        assert_eq!(
+            upcoming(1),
+            [date("2021-01-31"), date("2021-02-28"), date("2021-03-31")]
+        );
+        assert_eq!(
+            upcoming(2),
+            [date("2021-01-01"), date("2021-01-15"), date("2021-01-29")]
+        );
+
+        let error = compile_str("(every(0d; 2021-01-01)) [void > 1 > self]").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::InvalidNumber("0d".into()));
+    }
+
+    #[test]
+    fn month_days() {
+        let events = compile_str(
+            "calendar bank { 2021-04-30 }
+<a> (last day) [void > 1 > self]
+(second last friday) [void > 1 > self]
+(first monday) [void > 1 > self]
+(last workday) [void > 1 > self]
+(last workday(bank)) [void > 1 > self]",
+        )
+        .unwrap();
+        let schedules = events
+            .iter()
+            .map(|event| format!("{:?}", event.schedule))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            schedules[..3],
+            [
+                "MonthDay(-1)",
+                "Weekday { weekday: Fri, nth: -2 }",
+                "Weekday { weekday: Mon, nth: 1 }"
+            ]
+        );
+        let first = |event: &Event| event.schedule.upcoming(date("2021-04-01")).next();
+        assert_eq!(first(&events[3]), Some(date("2021-04-30")));
+        assert_eq!(first(&events[4]), Some(date("2021-04-29")));
+    }
+
+    #[test]
+    fn offset() {
+        let events = compile_str(
+            "<card> ((5 * *) + 21d) [void > 1 > self]
+(2021-03-31 - 1m + 2d) [void > 1 > self]
+(((1 * *) & (* 1/2 *)) + 1w) [void > 1 > self]
+(3 * *) + 1m [void > 1 > self]",
+        )
+        .unwrap();
+        let upcoming = |index: usize| {
+            events[index]
+                .schedule
+                .upcoming(date("2021-04-01"))
+                .take(3)
+                .collect::<Vec<_>>()
+        };
+        // The statement closing on 5 April is paid three weeks later
+        assert_eq!(
+            upcoming(0),
+            [date("2021-04-26"), date("2021-05-26"), date("2021-06-26")]
+        );
+        assert_eq!(
+            events[1].schedule.upcoming(date("2021-01-01")).next(),
+            Some(date("2021-03-02"))
+        );
+        assert_eq!(
+            upcoming(2),
+            [date("2021-05-08"), date("2021-07-08"), date("2021-09-08")]
+        );
+        assert_eq!(
+            upcoming(3),
+            [date("2021-04-03"), date("2021-05-03"), date("2021-06-03")]
+        );
+    }
+
+    #[test]
+    fn season() {
+        let events = compile_str(
+            "<heating> ((1 * *) & season(10-15..04-15)) [void > 1 > self]
+(season(02-29..03-01)) [void > 1 > self]",
+        )
+        .unwrap();
+        let upcoming = |index: usize| {
+            events[index]
+                .schedule
+                .between(date("2023-01-01"), date("2025-01-01"))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(upcoming(0).len(), 12);
+        assert_eq!(upcoming(0)[4], date("2023-11-01"));
+        assert_eq!(
+            upcoming(1),
+            [date("2023-03-01"), date("2024-02-29"), date("2024-03-01")]
+        );
+
+        let error = compile_str("(season(02-30..03-01)) [void > 1 > self]").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::InvalidDate("02-30".into()));
+
+        // Digits from other scripts are not month-days
+        let error =
+            compile_str("(season(\u{ff10}\u{ff11}-01..02-01)) [void > 1 > self]").unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::Syntax(_)));
+        let error =
+            compile_str("(season(01-01..\u{0662}\u{0660}-01)) [void > 1 > self]").unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::Syntax(_)));
+    }
+
+    #[test]
+    fn display_round_trip() {
+        let compile_one = |schedule: &str| {
+            compile_str(&format!(
+                "calendar bank {{ 12-25 observed, 2021-04-30 }}\n<a> {schedule} [void > 1 > self]"
+            ))
+            .unwrap()
+            .remove(0)
+            .schedule
+        };
+        for (source, expected) in [
+            ("(3 * *)", "(3 * *)"),
+            ("(1,15 jan-mar *)", "(1,15 jan-mar *)"),
+            ("(2021-03-31 - 1m)", "(2021-02-28)"),
+            ("(work)", "(work)"),
+            ("(work(2021-01-04, bank))", "(work(2021-01-04, bank))"),
+            ("(holidays(bank))", "(holidays(bank))"),
+            ("(every(2w; 2021-01-08))", "(every(2w; 2021-01-08))"),
+            ("(last day)", "(last day)"),
+            ("(second last friday)", "(second last friday)"),
+            ("(first workday(bank))", "(first workday(bank))"),
+            ("(season(12-01..01-15))", "(season(12-01..01-15))"),
+            ("((5 * *) + 21d)", "((5 * *) + 21d)"),
+            ("((5 * *) + 1m - 2d)", "(((5 * *) + 1m) - 2d)"),
+            (
+                "(roll(modified following; (31 * *); bank))",
+                "(roll(modified following; (31 * *); bank))",
+            ),
+            ("((3 * *) x 4)", "(take(4; (3 * *); 2021-01-01))"),
+            (
+                "(skip(2; (3 * *); 2021-06-01))",
+                "(skip(2; (3 * *); 2021-06-01))",
+            ),
+            ("(!(3 * *))", "(!(3 * *))"),
+            (
+                "((3 * *) | (5 * *) | (7 * *))",
+                "(((3 * *) | (5 * *)) | (7 * *))",
+            ),
+            ("((3 * *) & (* 1/2 *))", "((3 * *) & (* 1/2 *))"),
+            ("(by(work; (1 * *)))", "(by((work); (1 * *)))"),
+            ("((3 * *) < 2021-06-01)", "((3 * *) < (2021-06-01))"),
+            ("((3 * *) > 2021-06-01)", "((3 * *) > (2021-06-01))"),
+        ] {
+            let schedule = compile_one(source);
+            let printed = schedule.to_string();
+            assert_eq!(printed, expected);
+            let reparsed = compile_one(&printed);
+            assert_eq!(reparsed.to_string(), printed);
+            assert_eq!(format!("{reparsed:?}"), format!("{schedule:?}"));
+        }
+    }
+
+    #[test]
+    fn roll() {
+        let mut accounts = account::Interner::default();
+        let mut registry = account::Registry::default();
+        let events = compile(
+            Context {
+                holidays: Calendar::new([date("2024-04-01")]),
+                ..Context::new(
+                    &mut accounts,
+                    &mut registry,
+                    date("2024-01-01"),
+                    date("2024-12-31"),
+                )
+            },
+            "(roll(following; (1 * *))) [void > 1 > self]
+(roll(modified  following; (31 * *))) [void > 1 > self]
+(roll(modified preceding; 2024-06-01)) [void > 1 > self]",
+        )
+        .unwrap();
+        let upcoming = |index: usize| {
+            events[index]
+                .schedule
+                .upcoming(date("2024-03-01"))
+                .take(3)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            upcoming(0),
+            [date("2024-03-01"), date("2024-04-02"), date("2024-05-01")]
+        );
+        assert_eq!(
+            upcoming(1),
+            [date("2024-03-29"), date("2024-05-31"), date("2024-07-31")]
+        );
+        assert_eq!(upcoming(2), [date("2024-06-03")]);
+    }
+
+    #[test]
+    fn calendars() {
+        let (events, _) = compile_files(&[
+            (
+                "budget/main.mercury",
+                "calendar uk \"holidays/uk.csv\"
+calendar office { 12-24, 12-31, 2024-08-30 }
+(holidays(uk)) [void > 1 > self]
+((* * *) & !holidays(uk, office)) [void > 1 > self]
+(work(uk, 2024-08-23)) [void > 1 > self]
+(roll(preceding; (25 * *); uk)) [void > 1 > self]",
+            ),
+            (
+                "budget/holidays/uk.csv",
+                "# England and Wales
+01-01 observed, New Year's Day
+easter - 2d, Good Friday
+easter + 1d, Easter Monday
+first monday of may, Early May bank holiday
+last monday of may, Spring bank holiday
+last monday of august, Summer bank holiday
+12-25 observed, Christmas Day
+12-26 observed, Boxing Day
+",
+            ),
+        ]);
+        let events = events.unwrap();
+        let upcoming = |index: usize, from| {
+            events[index]
+                .schedule
+                .upcoming(date(from))
+                .take(3)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            upcoming(0, "2024-04-01"),
+            [date("2024-04-01"), date("2024-05-06"), date("2024-05-27")]
+        );
+        assert_eq!(
+            upcoming(1, "2024-08-24"),
+            [date("2024-08-24"), date("2024-08-25"), date("2024-08-27")]
+        );
+        assert_eq!(
+            upcoming(2, "2024-08-22"),
+            [date("2024-08-22"), date("2024-08-27"), date("2024-08-28")]
+        );
+        assert_eq!(
+            upcoming(3, "2024-11-01"),
+            [date("2024-11-25"), date("2024-12-24"), date("2025-01-24")]
+        );
+    }
+
+    #[test]
+    fn error_calendars() {
+        let error = compile_str("(holidays(uk)) [void > 1 > self]").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::UnknownCalendar("uk".into()));
+
+        let error = compile_str("calendar uk { 02-30 }").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::InvalidDate("02-30".into()));
+        let error = compile_str("calendar uk { 13-01 }").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::InvalidDate("13-01".into()));
+        let error = compile_str("calendar uk { 00-00 }").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::InvalidDate("00-00".into()));
+
+        // Only ASCII digits make a date
+        let error = compile_str("calendar uk { \u{ff10}\u{ff11}-\u{ff10}\u{ff11} }").unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::Syntax(_)));
+        let error =
+            compile_str("(\u{ff12}\u{ff10}\u{ff12}\u{ff14}-01-01) [void > 1 > self]").unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::Syntax(_)));
+
+        let error = compile_str("calendar uk { 01-01 }\ncalendar uk { 12-25 }").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Redefinition("uk".into()));
+
+        let (events, _) = compile_files(&[
+            ("budget/main.mercury", "calendar uk \"uk.txt\""),
+            ("budget/uk.txt", "01-01\n\nfirst moonday of may\n"),
+        ]);
+        let error = events.unwrap_err();
+        assert!(matches!(
+            error.kind,
+            ErrorKind::DataFile { ref path, ref message }
+                if path == "budget/uk.txt" && message.starts_with("line 3: ")
+        ));
+    }
+
+    #[test]
+    fn take_skip() {
+        let events = compile_str(
+            "((15 * *) > 2021-03-01)x12 [void > 1 > self]
+(take(2; every(1m; 2021-03-31))) [void > 1 > self]
+(skip(1; (2021-06-01 | 2021-07-01))) [void > 1 > self]",
+        )
+        .unwrap();
+        let upcoming = |index: usize| {
+            events[index]
+                .schedule
+                .upcoming(date("2021-04-01"))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(upcoming(0).len(), 11);
+        assert_eq!(upcoming(0).last(), Some(&date("2022-02-15")));
+        assert_eq!(upcoming(1), [date("2021-04-30")]);
+        assert_eq!(upcoming(2), [date("2021-07-01")]);
+
+        // Counted from the start, or from an anchor
+        let (events, accounts, registry) = compile_openings(
+            "<a> (15 * *)x12 [void > 1 > self]
+<b> (take(12; (15 * *); 2023-05-01)) [void > 1 > self]",
+        )
+        .unwrap();
+        let (a, b) = (accounts.get("a").unwrap(), accounts.get("b").unwrap());
+        assert_eq!(
+            events[0].schedule.upcoming(date("2024-01-01")).last(),
+            Some(date("2025-02-15"))
+        );
+        let mut timeline = crate::Timeline::new(&events, accounts, registry);
+        timeline
+            .process(date("2024-03-01"), date("2024-06-01"))
+            .for_each(drop);
+        assert_eq!(timeline.balances()[&a], Decimal::from(3));
+        assert_eq!(timeline.balances()[&b], Decimal::from(2));
+    }
+
+    /// The statements with every account and commodity by name, as they would be written.
+    fn outline(statements: &Statements, accounts: &account::Interner) -> String {
+        let name = |id| accounts.resolve(id).unwrap();
+        match statements {
+            Statements::List(acc, list) | Statements::Set(acc, list) => {
+                let inner = list
+                    .iter()
+                    .map(|stmt| outline(stmt, accounts))
+                    .collect::<Vec<_>>();
+                let (open, close) = match statements {
+                    Statements::List(..) => ("[", "]"),
+                    _ => ("{", "}"),
+                };
+                format!("<{}> {open}{}{close}", name(*acc), inner.join(", "))
+            }
+            Statements::Single(stmt) => format!(
+                "{} > {:?} > {}: {:?}",
+                name(stmt.from),
+                stmt.commodity.map(name),
+                name(stmt.to),
+                stmt.label
+            ),
+        }
+    }
+
+    #[test]
+    fn builder_equivalence() {
+        use crate::{builder::Transfer, EventBuilder, Resolve};
+
+        let (parsed, parsed_accounts, registry) = compile_openings(
+            "<current, savings>
+(1 * *) [void > 2_000 > self: Salary, self > 5 % > savings, savings > 100 y/m > self]
+<current>
+(every(1m; 2024-01-31)) + 1d <bills> {
+    self > 50 EUR > energy,
+    self > <current> * 1% > fees,
+    <b> [self > 1 > c],
+}",
+        )
+        .unwrap();
+
+        let mut accounts = account::Interner::default();
+        let built = vec![
+            EventBuilder::new(&mut accounts, Schedule::cron("1 * *").unwrap())
+                .account("current")
+                .account("savings")
+                .list(None, |stmts| {
+                    let this = stmts.id("self");
+                    stmts
+                        .transfer(Transfer::new("void", 2_000, "self").label("Salary"))
+                        .transfer(Transfer::new(
+                            "self",
+                            Expression::from(5).percent_of(this),
+                            "savings",
+                        ))
+                        .transfer(Transfer::new(
+                            "savings",
+                            Expression::from(100).ratio(1, 12),
+                            "self",
+                        ));
+                }),
+            EventBuilder::new(
+                &mut accounts,
+                Schedule::every(Period::Months(1), date("2024-01-31"))
+                    .offset(Shift::Later(Period::Days(1))),
+            )
+            .account("current")
+            .set(Some("bills"), |stmts| {
+                let fees = Expression::Mul(
+                    Box::new(stmts.balance("current")),
+                    Box::new(Expression::from(1).percent()),
+                );
+                stmts
+                    .transfer(Transfer::new("self", 50, "energy").commodity("EUR"))
+                    .transfer(Transfer::new("self", fees, "fees"))
+                    .list(Some("b"), |stmts| {
+                        stmts.transfer(Transfer::new("self", 1, "c"));
+                    });
+            }),
+        ];
+
+        let shape = |events: &[Event], accounts: &account::Interner| {
+            events
+                .iter()
+                .map(|event| {
+                    let names = event
+                        .accounts
+                        .iter()
+                        .map(|acc| accounts.resolve(*acc).unwrap().to_owned())
+                        .collect::<Vec<_>>();
+                    let operations = outline(&event.operations, accounts);
+                    (event.schedule.to_string(), names, operations)
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(shape(&parsed, &parsed_accounts), shape(&built, &accounts));
+
+        // The operations compute the same amounts
+        let mut parsed = crate::Timeline::new(&parsed, parsed_accounts, registry);
+        let mut built = crate::Timeline::new(&built, accounts, account::Registry::default());
+        for timeline in [&mut parsed, &mut built] {
+            timeline
+                .process(date("2024-03-01"), date("2024-06-01"))
+                .for_each(drop);
+        }
+        assert_eq!(parsed.dates().len(), 6);
+        assert_eq!(parsed.dates(), built.dates());
+        assert_eq!(
+            parsed.resolve(parsed.history()),
+            built.resolve(built.history())
+        );
+    }
 }
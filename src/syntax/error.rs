@@ -0,0 +1,222 @@
+use std::{
+    fmt::{self, Display},
+    path::Path,
+};
+
+use super::parser::{Node, Rule};
+
+/// A line and column within the source, both starting at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The region of the source an error refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: Location,
+    pub end: Location,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The source does not match the grammar
+    Syntax(String),
+    /// A date literal that does not exist in the calendar
+    InvalidDate(String),
+    /// A cron expression that the cron engine rejected
+    InvalidCron(String),
+    /// A number that could not be represented
+    InvalidNumber(String),
+    /// A keyword the compiler does not know how to handle
+    UnknownKeyword(String),
+    /// A function the compiler does not know how to handle
+    UnsupportedFunction(String),
+    /// A function called with the wrong number of arguments
+    ArgumentCount { function: String, found: usize },
+    /// An included file that could not be read
+    Include { path: String, message: String },
+    /// A file of exchange rates or holidays that could not be read
+    DataFile { path: String, message: String },
+    /// A file that includes itself, directly or indirectly
+    IncludeCycle { path: String },
+    /// A name bound by `let` more than once
+    Redefinition(String),
+    /// A name bound by `let` that is already a keyword
+    ReservedName(String),
+    /// An account declared in more than one commodity
+    CommodityMismatch { account: String, expected: String },
+    /// An account declared as more than one kind
+    KindMismatch { account: String, expected: String },
+    /// An account given more than one floor or ceiling
+    DuplicateLimit {
+        account: String,
+        limit: &'static str,
+    },
+    /// An account given more than one opening balance
+    DuplicateOpening(String),
+    /// A name that was never bound by `let`
+    UndefinedVariable(String),
+    /// A name bound to a value of the wrong type for where it is used
+    VariableType {
+        name: String,
+        expected: &'static str,
+    },
+    /// A calendar that was never declared
+    UnknownCalendar(String),
+    /// A schedule used where a single date is required
+    ExpectedDate(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    pub kind: ErrorKind,
+    pub span: Span,
+    /// The file the error occurred in, if the source came from one
+    path: Option<Box<Path>>,
+    /// The full text of the first line covered by the span
+    source_line: Box<str>,
+}
+
+pub type Result<T> = std::result::Result<T, CompileError>;
+
+impl CompileError {
+    pub(super) fn new(kind: ErrorKind, node: &Node) -> Self {
+        let span = node.as_span();
+        let (start, end) = (span.start_pos(), span.end_pos());
+        let location = |(line, column)| Location { line, column };
+        Self {
+            kind,
+            span: Span {
+                start: location(start.line_col()),
+                end: location(end.line_col()),
+            },
+            path: None,
+            source_line: start.line_of().trim_end_matches(['\r', '\n']).into(),
+        }
+    }
+
+    /// Attribute the error to `path`, unless it already came from a more specific file.
+    pub(super) fn or_path(mut self, path: &Path) -> Self {
+        self.path.get_or_insert_with(|| path.into());
+        self
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn source_line(&self) -> &str {
+        &self.source_line
+    }
+}
+
+impl From<pest::error::Error<Rule>> for CompileError {
+    fn from(error: pest::error::Error<Rule>) -> Self {
+        use pest::error::LineColLocation;
+        let ((line, column), (end_line, end_column)) = match error.line_col {
+            LineColLocation::Pos(pos) => (pos, pos),
+            LineColLocation::Span(start, end) => (start, end),
+        };
+        Self {
+            kind: ErrorKind::Syntax(error.variant.message().into_owned()),
+            span: Span {
+                start: Location { line, column },
+                end: Location {
+                    line: end_line,
+                    column: end_column,
+                },
+            },
+            path: None,
+            source_line: error.line().trim_end_matches(['\r', '\n']).into(),
+        }
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::Syntax(message) => write!(f, "syntax error: {message}"),
+            ErrorKind::InvalidDate(date) => write!(f, "invalid date `{date}`"),
+            ErrorKind::InvalidCron(message) => write!(f, "invalid cron expression: {message}"),
+            ErrorKind::InvalidNumber(number) => write!(f, "invalid number `{number}`"),
+            ErrorKind::UnknownKeyword(keyword) => write!(f, "unknown keyword `{keyword}`"),
+            ErrorKind::UnsupportedFunction(func) => write!(f, "unsupported function `{func}`"),
+            ErrorKind::ArgumentCount { function, found } => {
+                write!(
+                    f,
+                    "wrong number of arguments to `{function}`: found {found}"
+                )
+            }
+            ErrorKind::Include { path, message } => {
+                write!(f, "could not include `{path}`: {message}")
+            }
+            ErrorKind::DataFile { path, message } => {
+                write!(f, "could not load `{path}`: {message}")
+            }
+            ErrorKind::IncludeCycle { path } => write!(f, "`{path}` includes itself"),
+            ErrorKind::Redefinition(name) => write!(f, "`{name}` is already defined"),
+            ErrorKind::ReservedName(name) => {
+                write!(f, "`{name}` is a keyword and cannot be bound by `let`")
+            }
+            ErrorKind::CommodityMismatch { account, expected } => {
+                write!(f, "`{account}` is already held in {expected}")
+            }
+            ErrorKind::KindMismatch { account, expected } => {
+                write!(f, "`{account}` is already declared as {expected}")
+            }
+            ErrorKind::DuplicateLimit { account, limit } => {
+                write!(f, "`{account}` already has a {limit}")
+            }
+            ErrorKind::DuplicateOpening(account) => {
+                write!(f, "`{account}` already has an opening balance")
+            }
+            ErrorKind::UndefinedVariable(name) => write!(f, "`{name}` is not defined"),
+            ErrorKind::VariableType { name, expected } => {
+                write!(f, "`{name}` has the wrong type, expected {expected}")
+            }
+            ErrorKind::UnknownCalendar(name) => write!(f, "no calendar named `{name}`"),
+            ErrorKind::ExpectedDate(found) => write!(f, "expected a single date, found `{found}`"),
+        }
+    }
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Span { start, end } = self.span;
+        let gutter = start.line.to_string().len();
+
+        // Underline to the end of the span, or the end of the line if the span continues past it
+        let width = if end.line == start.line {
+            end.column.saturating_sub(start.column)
+        } else {
+            (self.source_line.chars().count() + 1).saturating_sub(start.column)
+        }
+        .max(1);
+
+        writeln!(f, "error: {}", self.kind)?;
+        match &self.path {
+            Some(path) => writeln!(
+                f,
+                "{:gutter$}--> {}:{}:{}",
+                "",
+                path.display(),
+                start.line,
+                start.column
+            )?,
+            None => writeln!(f, "{:gutter$}--> {}:{}", "", start.line, start.column)?,
+        }
+        writeln!(f, "{:gutter$} |", "")?;
+        writeln!(f, "{} | {}", start.line, self.source_line)?;
+        write!(
+            f,
+            "{:gutter$} | {}{}",
+            "",
+            " ".repeat(start.column - 1),
+            "^".repeat(width)
+        )
+    }
+}
+
+impl std::error::Error for CompileError {}
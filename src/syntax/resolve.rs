@@ -0,0 +1,70 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+/// Finds and reads the files named by `include` declarations.
+pub trait Resolver {
+    /// The path of `include`, relative to the file that included it.
+    /// Two includes of the same file must produce equal paths.
+    fn locate(&self, include: &str, from: Option<&Path>) -> PathBuf;
+
+    /// The contents of a file previously returned by [`Resolver::locate`].
+    fn read(&self, path: &Path) -> io::Result<String>;
+}
+
+/// Resolves includes against the local filesystem.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Filesystem;
+
+impl Resolver for Filesystem {
+    fn locate(&self, include: &str, from: Option<&Path>) -> PathBuf {
+        let path = relative_to(include, from);
+        std::fs::canonicalize(&path).unwrap_or_else(|_| normalise(&path))
+    }
+
+    fn read(&self, path: &Path) -> io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+}
+
+/// Resolves includes against an in-memory map of files.
+impl Resolver for HashMap<PathBuf, String> {
+    fn locate(&self, include: &str, from: Option<&Path>) -> PathBuf {
+        normalise(&relative_to(include, from))
+    }
+
+    fn read(&self, path: &Path) -> io::Result<String> {
+        self.get(path).cloned().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no such file `{}`", path.display()),
+            )
+        })
+    }
+}
+
+fn relative_to(include: &str, from: Option<&Path>) -> PathBuf {
+    match from.and_then(Path::parent) {
+        Some(dir) => dir.join(include),
+        None => PathBuf::from(include),
+    }
+}
+
+/// Remove `.` and `..` components without touching the filesystem.
+pub fn normalise(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(out.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                out.pop();
+            }
+            component => out.push(component),
+        }
+    }
+    out
+}
@@ -1,18 +1,37 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeSet, HashMap},
+    iter,
+};
 
 use crate::{
-    account, process,
+    account, fx, process,
     transaction::{self, View},
     Datestamp, Event,
 };
 
+/// The balances of an account on each date from the first date it had one.
+type Series = (Datestamp, Vec<account::Money>);
+
 #[derive(Debug)]
 pub struct Timeline<'e> {
     stack: account::Stack,
     interner: account::Interner,
+    registry: account::Registry,
     events: &'e [Event],
+    /// Opening balances with a date that have yet to take effect, latest first
+    openings: Vec<(Datestamp, account::ID, account::Money)>,
     datestamps: Vec<Datestamp>,
+    /// Balances including every account below, by the accounts they are for
     history: HashMap<account::ID, (Datestamp, Vec<account::Money>)>,
+    /// Balances of each account alone
+    own_history: HashMap<account::ID, (Datestamp, Vec<account::Money>)>,
+    /// The account directly above each account in the hierarchy
+    parents: HashMap<account::ID, account::ID>,
+    missing_rates: BTreeSet<fx::MissingRate>,
+    /// The indices of the events with no dates in the last period processed
+    idle: Vec<usize>,
+    policy: account::Policy,
+    violations: Vec<account::Violation<'e>>,
 }
 
 #[derive(Debug)]
@@ -22,59 +41,394 @@ pub struct Moment<'t> {
 }
 
 impl<'e> Timeline<'e> {
-    pub fn new(events: &'e [Event], mut interner: account::Interner) -> Self {
-        let stack = account::Stack::new(account::Symbols::new(&mut interner));
-        Self {
+    pub fn new(
+        events: &'e [Event],
+        mut interner: account::Interner,
+        registry: account::Registry,
+    ) -> Self {
+        let mut stack = account::Stack::new(account::Symbols::new(&mut interner));
+        let mut openings = Vec::new();
+        for (acc, opening) in registry.openings() {
+            match opening.date {
+                Some(date) => openings.push((date, acc, opening.balance)),
+                None => stack[acc] = opening.balance,
+            }
+        }
+        openings.sort_by_key(|(date, ..)| std::cmp::Reverse(*date));
+        let mut timeline = Self {
             stack,
             interner,
+            registry,
             events,
+            openings,
             datestamps: Default::default(),
             history: Default::default(),
+            own_history: Default::default(),
+            parents: Default::default(),
+            missing_rates: Default::default(),
+            idle: Default::default(),
+            policy: Default::default(),
+            violations: Default::default(),
+        };
+        let accounts = timeline
+            .interner
+            .iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        for account in accounts {
+            timeline.link(account);
+        }
+        timeline
+    }
+
+    /// Add an account and every account above it to the hierarchy.
+    fn link(&mut self, account: account::ID) {
+        if self.parents.contains_key(&account) {
+            return;
+        }
+        let name = self.interner.resolve(account).unwrap();
+        if let Some(parent) = account::parent(name).map(str::to_owned) {
+            let parent = self.interner.get_or_intern(parent);
+            self.parents.insert(account, parent);
+            self.link(parent);
+        }
+    }
+
+    /// The accounts above `account`, nearest first.
+    fn ancestors(&self, account: account::ID) -> impl Iterator<Item = account::ID> + '_ {
+        iter::successors(self.parents.get(&account), |acc| self.parents.get(acc)).copied()
+    }
+
+    /// How many levels `account` is below `root`, if it is in the subtree of `root` at all.
+    fn depth_below(&self, account: account::ID, root: account::ID) -> Option<usize> {
+        iter::once(account)
+            .chain(self.ancestors(account))
+            .position(|acc| acc == root)
+    }
+
+    /// The balances of each account, with every account above it also including them.
+    fn roll_up(
+        &self,
+        balances: impl IntoIterator<Item = (account::ID, account::Money)>,
+    ) -> HashMap<account::ID, account::Money> {
+        let mut rolled = HashMap::<_, account::Money>::new();
+        for (acc, balance) in balances {
+            for acc in iter::once(acc).chain(self.ancestors(acc)) {
+                *rolled.entry(acc).or_default() += balance;
+            }
+        }
+        rolled
+    }
+
+    /// The histories of each account, with every account above it also including them.
+    fn roll_up_history(
+        &self,
+        history: impl IntoIterator<Item = (account::ID, Series)>,
+    ) -> HashMap<account::ID, (Datestamp, Vec<account::Money>)> {
+        let mut rolled = HashMap::<_, (Datestamp, Vec<account::Money>)>::new();
+        for (acc, (start, balances)) in history {
+            for acc in iter::once(acc).chain(self.ancestors(acc)) {
+                let (total_start, totals) = rolled.entry(acc).or_insert((start, Vec::new()));
+                // Every history ends on the latest date, so they line up from the end
+                if totals.len() < balances.len() {
+                    let missing = balances.len() - totals.len();
+                    totals.splice(0..0, iter::repeat_n(account::Money::ZERO, missing));
+                    *total_start = start;
+                }
+                let offset = totals.len() - balances.len();
+                for (total, balance) in totals[offset..].iter_mut().zip(&balances) {
+                    *total += *balance;
+                }
+            }
         }
+        rolled
     }
 
+    /// Choose what happens to statements that would take an account beyond its limits.
+    pub fn set_policy(&mut self, policy: account::Policy) {
+        self.policy = policy;
+    }
+
+    /// Process the events from `from` until, but not including, `to`.
+    ///
+    /// Opening balances dated before `from` are rolled forward through the events from their
+    /// date, without recording them in the history. Only those accounts are rolled forward, so
+    /// every other account starts `from` as it was. Under [`account::Policy::Abort`], processing
+    /// stops at the first event that breaks a limit.
     pub fn process<'a>(
         &'a mut self,
         from: Datestamp,
         to: Datestamp,
     ) -> impl Iterator<Item = Moment<'e>> + 'a {
-        Event::timeline(self.events.iter(), from.clone())
-            .take_while(move |(date, _)| date < &to)
-            .map(|(date, event)| {
-                process::event(date, event, &mut self.stack, &mut self.interner);
-
-                // Update the history with the current balances
-                self.datestamps.push(date);
-                for (acc, bal) in self.stack.balances() {
-                    self.history
-                        .entry(acc)
-                        .or_insert_with(move || (date, Vec::with_capacity(1)))
-                        .1
-                        .push(bal);
+        let events = self.events;
+        let rolled = self
+            .openings
+            .iter()
+            .filter(|(date, ..)| date < &from)
+            .map(|&(_, acc, _)| acc)
+            .collect::<Vec<_>>();
+        if let Some(&(start, ..)) = self.openings.last().filter(|_| !rolled.is_empty()) {
+            let before = self.stack.clone();
+            for (date, event) in Event::timeline(events.iter(), start, from) {
+                if self.aborted() {
+                    break;
                 }
+                self.apply(date, event);
+            }
+            let after = std::mem::replace(&mut self.stack, before);
+            for acc in rolled {
+                self.stack[acc] = after[acc];
+            }
+        }
+        self.open(from);
 
-                Moment {
-                    date: date.clone(),
-                    transaction: transaction::Source {
-                        date: date.clone(),
-                        from: event.accounts[0],
-                        to: event.accounts[0],
-                        amount: 100.0,
-                        label: None,
-                    },
-                }
+        // The opening balances are the first point in the history
+        if self.datestamps.is_empty() && self.stack.balances().next().is_some() {
+            self.record(from);
+        }
+
+        self.idle = events
+            .iter()
+            .enumerate()
+            .filter(|(_, event)| event.schedule.between(from, to).next().is_none())
+            .map(|(index, _)| index)
+            .collect();
+
+        Event::timeline(events.iter(), from, to).map_while(|(date, (index, event))| {
+            if self.aborted() {
+                return None;
+            }
+            self.apply(date, (index, event));
+            self.record(date);
+
+            Some(Moment {
+                date,
+                transaction: transaction::Source {
+                    date,
+                    from: event.accounts[0],
+                    to: event.accounts[0],
+                    amount: account::Money::from(100),
+                    label: None,
+                },
             })
+        })
+    }
+
+    fn apply(&mut self, date: Datestamp, (index, event): (usize, &'e Event)) {
+        self.open(date);
+        process::event(
+            date,
+            (index, event),
+            &mut self.stack,
+            &mut self.interner,
+            &self.registry,
+            &mut self.missing_rates,
+            process::Constraints {
+                policy: self.policy,
+                violations: &mut self.violations,
+            },
+        );
+    }
+
+    /// Apply the opening balances that take effect on or before `date`.
+    fn open(&mut self, date: Datestamp) {
+        while let Some(&(_, acc, balance)) = self.openings.last().filter(|(at, ..)| at <= &date) {
+            self.stack[acc] = balance;
+            self.openings.pop();
+        }
+    }
+
+    /// Update the history with the current balances.
+    fn record(&mut self, date: Datestamp) {
+        self.datestamps.push(date);
+        let balances = self.stack.balances().collect::<Vec<_>>();
+        for &(acc, _) in &balances {
+            self.link(acc);
+        }
+        let push = |history: &mut HashMap<_, (Datestamp, Vec<_>)>, (acc, bal)| {
+            history
+                .entry(acc)
+                .or_insert_with(move || (date, Vec::with_capacity(1)))
+                .1
+                .push(bal);
+        };
+        for (acc, bal) in self.roll_up(balances.iter().copied()) {
+            push(&mut self.history, (acc, bal));
+        }
+        for entry in balances {
+            push(&mut self.own_history, entry);
+        }
+    }
+
+    /// The ID of an account or commodity by name, if it was ever mentioned.
+    pub fn lookup(&self, name: &str) -> Option<account::ID> {
+        self.interner.get(name)
+    }
+
+    pub fn registry(&self) -> &account::Registry {
+        &self.registry
     }
 
     pub fn dates(&self) -> &[Datestamp] {
         &self.datestamps
     }
 
+    /// The balance of every account on each date, including the accounts below it.
+    ///
+    /// Accounts below a parent are summed as they are, whatever their commodities.
     pub fn history(&self) -> &HashMap<account::ID, (Datestamp, Vec<account::Money>)> {
         &self.history
     }
+
+    /// The history with every account that has a commodity converted to `base`, at the rate on
+    /// each date, before parents sum the accounts below them.
+    pub fn history_in(
+        &self,
+        base: account::Commodity,
+    ) -> Result<HashMap<account::ID, (Datestamp, Vec<account::Money>)>, fx::MissingRate> {
+        self.own_history_in(base)
+            .map(|history| self.roll_up_history(history))
+    }
+
+    /// The balances of each account alone, converted to `base`.
+    fn own_history_in(
+        &self,
+        base: account::Commodity,
+    ) -> Result<Vec<(account::ID, Series)>, fx::MissingRate> {
+        self.own_history
+            .iter()
+            .map(|(acc, (start, balances))| {
+                let dates = &self.datestamps[self.datestamps.len() - balances.len()..];
+                let commodity = self.registry.commodity(*acc);
+                let balances = balances
+                    .iter()
+                    .zip(dates)
+                    .map(|(balance, date)| {
+                        self.registry
+                            .rates()
+                            .convert(*balance, commodity, Some(base), *date)
+                            .map(|balance| self.registry.precision().round(balance))
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok((*acc, (*start, balances)))
+            })
+            .collect()
+    }
+
+    /// The kind of an account, or of the nearest account above it with one.
+    pub fn kind(&self, account: account::ID) -> Option<account::Kind> {
+        iter::once(account)
+            .chain(self.ancestors(account))
+            .find_map(|acc| self.registry.get(acc)?.kind)
+    }
+
+    /// The sum of every account of the given kinds on each date, converted to `base` if given.
+    fn total(
+        &self,
+        kinds: &[account::Kind],
+        base: Option<account::Commodity>,
+    ) -> Result<Vec<account::Money>, fx::MissingRate> {
+        let history = match base {
+            Some(base) => self.own_history_in(base)?,
+            None => self
+                .own_history
+                .iter()
+                .map(|(acc, history)| (*acc, history.clone()))
+                .collect(),
+        };
+        let mut totals = vec![account::Money::ZERO; self.datestamps.len()];
+        for (acc, (_, balances)) in history {
+            if !self.kind(acc).is_some_and(|kind| kinds.contains(&kind)) {
+                continue;
+            }
+            let offset = totals.len() - balances.len();
+            for (total, balance) in totals[offset..].iter_mut().zip(balances) {
+                *total += balance;
+            }
+        }
+        Ok(totals)
+    }
+
+    /// Assets less liabilities on each date.
+    pub fn net_worth(
+        &self,
+        base: Option<account::Commodity>,
+    ) -> Result<Vec<account::Money>, fx::MissingRate> {
+        // Liabilities are already below zero
+        self.total(&[account::Kind::Asset, account::Kind::Liability], base)
+    }
+
+    /// The income earned so far on each date, as a positive amount.
+    pub fn income(
+        &self,
+        base: Option<account::Commodity>,
+    ) -> Result<Vec<account::Money>, fx::MissingRate> {
+        self.total(&[account::Kind::Income], base)
+            .map(|totals| totals.into_iter().map(|total| -total).collect())
+    }
+
+    /// The expenses paid so far on each date.
+    pub fn expenses(
+        &self,
+        base: Option<account::Commodity>,
+    ) -> Result<Vec<account::Money>, fx::MissingRate> {
+        self.total(&[account::Kind::Expense], base)
+    }
+
+    /// Accounts whose current balance has the opposite sign to their kind, such as an expense
+    /// that money has flowed out of. Sorted by account.
+    pub fn wrong_signs(&self) -> Vec<account::WrongSign> {
+        let mut wrong = self
+            .balances()
+            .into_iter()
+            .filter_map(|(account, balance)| {
+                let kind = self.kind(account)?;
+                (!kind.expects(balance)).then_some(account::WrongSign {
+                    account,
+                    kind,
+                    balance,
+                })
+            })
+            .collect::<Vec<_>>();
+        wrong.sort_by_key(|wrong| wrong.account);
+        wrong
+    }
+
+    /// Conversions made one to one, for want of a rate.
+    pub fn missing_rates(&self) -> &BTreeSet<fx::MissingRate> {
+        &self.missing_rates
+    }
+
+    /// Every statement that would have taken an account beyond its limits, in order.
+    pub fn violations(&self) -> &[account::Violation<'e>] {
+        &self.violations
+    }
+
+    /// Whether a violation under [`account::Policy::Abort`] has stopped processing.
+    pub fn aborted(&self) -> bool {
+        self.policy == account::Policy::Abort && !self.violations.is_empty()
+    }
+
+    /// The indices of the events that never occurred in the last period processed.
+    pub fn idle(&self) -> &[usize] {
+        &self.idle
+    }
+
+    /// The current balance of every account, including the accounts below it.
     pub fn balances(&self) -> HashMap<account::ID, account::Money> {
-        self.stack.balances().collect()
+        self.roll_up(self.stack.balances())
+    }
+
+    /// The balances of `root` and the accounts below it, down to `depth` levels below `root`.
+    /// Each includes every account below it, however deep.
+    pub fn subtree(&self, root: account::ID, depth: usize) -> HashMap<account::ID, account::Money> {
+        self.balances()
+            .into_iter()
+            .filter(|(acc, _)| {
+                self.depth_below(*acc, root)
+                    .is_some_and(|below| below <= depth)
+            })
+            .collect()
     }
 }
 
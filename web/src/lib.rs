@@ -1,3 +1,5 @@
+use std::{collections::HashMap, path::PathBuf};
+
 use mercury::Resolve;
 use wasm_bindgen::prelude::*;
 
@@ -11,7 +13,8 @@ fn initialise() {
 #[wasm_bindgen(getter_with_clone)]
 pub struct Account {
     pub name: String,
-    pub history: Vec<f64>,
+    /// Balances as exact decimal strings, such as `"1012.88"`, for JavaScript numbers would round them
+    pub history: Vec<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -21,26 +24,35 @@ pub struct Output {
     pub accounts: Vec<Account>,
 }
 
+type History = (
+    Vec<mercury::Datestamp>,
+    Vec<(String, Vec<mercury::account::Money>)>,
+);
+
+type Files = HashMap<PathBuf, String>;
+
 fn parse_from_until(
-    input: &str,
+    files: &Files,
+    entry: &str,
     from: mercury::Datestamp,
     to: mercury::Datestamp,
-) -> (
-    Vec<mercury::Datestamp>,
-    Vec<(String, Vec<mercury::account::Money>)>,
-) {
+) -> Result<History, String> {
     let mut accounts = mercury::account::Interner::default();
+    let mut registry = mercury::account::Registry::default();
 
-    let events = mercury::syntax::compile(
-        mercury::syntax::Context {
-            accounts: &mut accounts,
-            date_start: from,
-            date_end: to,
-        },
+    let entry = mercury::syntax::normalise(entry.as_ref());
+    let input = files
+        .get(&entry)
+        .ok_or_else(|| format!("no such file `{}`", entry.display()))?;
+    let events = mercury::syntax::compile_file(
+        mercury::syntax::Context::new(&mut accounts, &mut registry, from, to),
+        files,
+        &entry,
         input,
-    );
+    )
+    .map_err(|e| e.to_string())?;
 
-    let mut timeline = mercury::Timeline::new(&events, accounts);
+    let mut timeline = mercury::Timeline::new(&events, accounts, registry);
     timeline.process(from, to).for_each(drop);
 
     let history = timeline.resolve(timeline.history());
@@ -49,7 +61,7 @@ fn parse_from_until(
         .into_iter()
         .map(|(acc, (_, balances))| {
             (acc.to_owned(), {
-                let mut out = vec![0.0; dates.len() - balances.len()];
+                let mut out = vec![mercury::Decimal::ZERO; dates.len() - balances.len()];
                 out.reserve_exact(balances.len());
                 out.extend(balances);
                 out
@@ -57,16 +69,34 @@ fn parse_from_until(
         })
         .collect::<Vec<_>>();
 
-    (dates.into(), full_history)
+    Ok((dates.into(), full_history))
 }
 
 #[wasm_bindgen]
 pub fn parse(input: &str, from: &str, to: &str) -> Result<Output, String> {
+    const ENTRY: &str = "main.mercury";
+    let files = Files::from([(ENTRY.into(), input.to_owned())]);
+    parse_project(&files, ENTRY, from, to)
+}
+
+/// Parse a ledger split over several files, given as an object mapping paths to their contents.
+#[wasm_bindgen]
+pub fn parse_files(files: JsValue, entry: &str, from: &str, to: &str) -> Result<Output, String> {
+    let files: HashMap<String, String> =
+        serde_wasm_bindgen::from_value(files).map_err(|e| e.to_string())?;
+    let files = files
+        .into_iter()
+        .map(|(path, source)| (mercury::syntax::normalise(path.as_ref()), source))
+        .collect::<Files>();
+    parse_project(&files, entry, from, to)
+}
+
+fn parse_project(files: &Files, entry: &str, from: &str, to: &str) -> Result<Output, String> {
     const DATE_FORMAT: &str = "%Y-%m-%d";
 
     let from = mercury::Datestamp::parse_from_str(from, DATE_FORMAT).map_err(|e| e.to_string())?;
     let to = mercury::Datestamp::parse_from_str(to, DATE_FORMAT).map_err(|e| e.to_string())?;
-    let (dates, accounts) = parse_from_until(input, from, to);
+    let (dates, accounts) = parse_from_until(files, entry, from, to)?;
 
     Ok(Output {
         dates: dates
@@ -75,7 +105,10 @@ pub fn parse(input: &str, from: &str, to: &str) -> Result<Output, String> {
             .collect(),
         accounts: accounts
             .into_iter()
-            .map(|(name, history)| Account { name, history })
+            .map(|(name, history)| Account {
+                name,
+                history: history.iter().map(ToString::to_string).collect(),
+            })
             .collect(),
     })
 }